@@ -0,0 +1,185 @@
+//! The handful of Notion API calls that page creation and migration make. The real
+//! client is one implementation; [`FakeNotion`](crate::FakeNotion) is another, for
+//! testing without a network or an API key.
+
+use std::future::Future;
+use std::time::Duration;
+
+use notion_client::endpoints::blocks::append::request::AppendBlockChildrenRequest;
+use notion_client::endpoints::blocks::update::request::UpdateABlockRequest;
+use notion_client::endpoints::pages::create::request::CreateAPageRequest;
+use notion_client::endpoints::Client;
+use notion_client::objects::block::Block;
+use notion_client::objects::page::Page as NotionPage;
+use notion_client::NotionClientError;
+
+use crate::{RateLimiter, RetryPolicy};
+
+/// Create pages, append and update blocks, and fetch pages.
+pub trait NotionApi: Send + Sync {
+    fn create_page(&self, request: CreateAPageRequest) -> impl Future<Output = Result<NotionPage, ApiError>> + Send;
+
+    /// Returns the newly created top-level blocks, in order.
+    fn append_children(
+        &self,
+        parent_id: &str,
+        request: AppendBlockChildrenRequest,
+    ) -> impl Future<Output = Result<Vec<Block>, ApiError>> + Send;
+
+    fn update_block(
+        &self,
+        block_id: &str,
+        request: UpdateABlockRequest,
+    ) -> impl Future<Output = Result<Option<Block>, ApiError>> + Send;
+
+    fn retrieve_page(&self, page_id: &str) -> impl Future<Output = Result<NotionPage, ApiError>> + Send;
+
+    /// How hard to try when a request fails in a way that might not happen again.
+    fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy::default()
+    }
+
+    /// What every request, retries included, waits on before it goes out.
+    fn rate_limiter(&self) -> Option<&RateLimiter> {
+        None
+    }
+}
+
+/// A failed Notion request, with enough detail to decide whether to try it again.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ApiError {
+    /// Notion answered, with an error status.
+    Status {
+        status: u16,
+        code: String,
+        message: String,
+        /// How long Notion asked us to wait before trying again, if it said.
+        retry_after: Option<Duration>,
+    },
+    /// We never got an answer: the connection failed, dropped, or timed out.
+    Transport(String),
+    /// The request couldn't be encoded, or the response made no sense.
+    Invalid(String),
+}
+
+impl ApiError {
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            ApiError::Status { status, .. } => Some(*status),
+            _ => None,
+        }
+    }
+
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            ApiError::Status { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+
+    /// Did Notion turn the request away without doing anything? Conflicts, rate limits,
+    /// and an unavailable service leave nothing behind, so any request can go again.
+    pub fn is_retryable(&self) -> bool {
+        matches!(self.status(), Some(409 | 429 | 503))
+    }
+
+    /// Might trying again get past this, for a request that does no harm if it happens
+    /// twice? Server errors and lost connections could hide a request Notion applied, so
+    /// only a request that sets something rather than adding something should repeat them.
+    pub fn is_retryable_if_idempotent(&self) -> bool {
+        match self {
+            ApiError::Status { status, .. } => matches!(status, 409 | 429 | 500 | 502 | 503 | 504),
+            ApiError::Transport(_) => true,
+            ApiError::Invalid(_) => false,
+        }
+    }
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ApiError::Status {
+                status, code, message, ..
+            } => write!(f, "Notion responded {status} {code}: {message}"),
+            ApiError::Transport(message) => write!(f, "could not reach Notion: {message}"),
+            ApiError::Invalid(message) => write!(f, "bad Notion request or response: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+impl From<NotionClientError> for ApiError {
+    fn from(value: NotionClientError) -> Self {
+        match value {
+            NotionClientError::InvalidStatusCode { error } => ApiError::Status {
+                status: u16::try_from(error.status).unwrap_or(500),
+                code: error.code,
+                message: error.message,
+                retry_after: None,
+            },
+            NotionClientError::FailedToRequest { source } | NotionClientError::FailedToText { source } => {
+                ApiError::Transport(source.to_string())
+            }
+            other => ApiError::Invalid(other.to_string()),
+        }
+    }
+}
+
+/// The notion-client crate's client works, but it can't tell us about `Retry-After`
+/// headers. [`NotionHttp`](crate::NotionHttp) can.
+impl NotionApi for Client {
+    async fn create_page(&self, request: CreateAPageRequest) -> Result<NotionPage, ApiError> {
+        Ok(self.pages.create_a_page(request).await?)
+    }
+
+    async fn append_children(
+        &self,
+        parent_id: &str,
+        request: AppendBlockChildrenRequest,
+    ) -> Result<Vec<Block>, ApiError> {
+        let response = self.blocks.append_block_children(parent_id, request).await?;
+        Ok(response.results)
+    }
+
+    async fn update_block(&self, block_id: &str, request: UpdateABlockRequest) -> Result<Option<Block>, ApiError> {
+        let response = self.blocks.update_a_block(block_id, request).await?;
+        Ok(response.block)
+    }
+
+    async fn retrieve_page(&self, page_id: &str) -> Result<NotionPage, ApiError> {
+        Ok(self.pages.retrieve_a_page(page_id, None).await?)
+    }
+}
+
+/// Stands in for a client when planning, which never sends anything. Every request
+/// fails, so a planned request that slipped through would show up as an error.
+pub(crate) struct Offline;
+
+impl Offline {
+    fn refuse() -> ApiError {
+        ApiError::Invalid("planning makes no requests".to_string())
+    }
+}
+
+impl NotionApi for Offline {
+    async fn create_page(&self, _request: CreateAPageRequest) -> Result<NotionPage, ApiError> {
+        Err(Offline::refuse())
+    }
+
+    async fn append_children(
+        &self,
+        _parent_id: &str,
+        _request: AppendBlockChildrenRequest,
+    ) -> Result<Vec<Block>, ApiError> {
+        Err(Offline::refuse())
+    }
+
+    async fn update_block(&self, _block_id: &str, _request: UpdateABlockRequest) -> Result<Option<Block>, ApiError> {
+        Err(Offline::refuse())
+    }
+
+    async fn retrieve_page(&self, _page_id: &str) -> Result<NotionPage, ApiError> {
+        Err(Offline::refuse())
+    }
+}
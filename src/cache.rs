@@ -42,7 +42,7 @@ impl Cache {
         let idset: HashSet<Uuid> = std::fs::read_dir(root.as_str())
             .into_diagnostic()?
             .filter_map(|xs| match xs {
-                Ok(fname) => match fname.file_name().to_string_lossy().split('_').last() {
+                Ok(fname) => match fname.file_name().to_string_lossy().split('_').next_back() {
                     Some(idstr) => match idstr.split('.').next() {
                         Some(base) => Uuid::try_from(base).ok(),
                         None => None,
@@ -79,7 +79,7 @@ impl Cache {
                     let file_name = fname.file_name();
                     let basename = file_name.to_string_lossy();
                     if basename.starts_with("page") {
-                        if let Some(stem) = basename.split('_').last() {
+                        if let Some(stem) = basename.split('_').next_back() {
                             let idstr = stem.replace(".json", "");
                             Uuid::try_parse(idstr.as_str()).ok()
                         } else {
@@ -112,7 +112,7 @@ impl Cache {
                 }
             })
             .collect();
-        content.sort_by(|left, right| left.0.to_lowercase().cmp(&right.0.to_lowercase()));
+        content.sort_by_key(|entry| entry.0.to_lowercase());
 
         for (title, id) in content {
             println!("    {}    {}", id.bold(), title.green());
@@ -0,0 +1,771 @@
+//! Notion blocks back to Markdown, the other way from [`convert`](crate::convert).
+//!
+//! Everything `convert` makes comes back as the Markdown that would make it again:
+//! admonitions as `> [!NOTE]`, toggles as `<details>`, image captions as alt text, and so
+//! on. Notion-only blocks get the nearest Markdown we have. Some things have no Markdown
+//! at all, like text colors and column layouts, and those are left out.
+
+use notion_client::objects::block::*;
+use notion_client::objects::file::File;
+use notion_client::objects::parent::Parent;
+use notion_client::objects::rich_text::{self, Annotations, Mention, RichText};
+
+use crate::languages;
+use crate::Admonition;
+
+/// Render Notion blocks as GFM Markdown.
+pub fn blocks_to_markdown(blocks: &[Block]) -> String {
+    let mut markdown = render_blocks(blocks);
+    if !markdown.is_empty() {
+        markdown.push('\n');
+    }
+    markdown
+}
+
+/// Blocks separated by blank lines, except that list items of one kind stay together.
+fn render_blocks(blocks: &[Block]) -> String {
+    let mut output = String::new();
+    let mut previous: Option<&BlockType> = None;
+    let mut number = 0;
+    let mut in_footnotes = false;
+    let mut idx = 0;
+    while idx < blocks.len() {
+        let block = &blocks[idx];
+        idx += 1;
+        number = match (&block.block_type, previous) {
+            (BlockType::NumberedListItem { .. }, Some(BlockType::NumberedListItem { .. })) => number + 1,
+            _ => 1,
+        };
+        let rendered = match &block.block_type {
+            // `convert` adds this heading itself, so it would turn up twice.
+            BlockType::Heading2 { heading_2 } if is_footnotes_heading(heading_2, blocks.get(idx)) => {
+                in_footnotes = true;
+                None
+            }
+            BlockType::Image { image } => {
+                let caption = blocks.get(idx).and_then(image_caption);
+                if caption.is_some() {
+                    idx += 1;
+                }
+                Some(render_image(image, caption.unwrap_or_default().as_str()))
+            }
+            block_type => render_block(block, block_type, number),
+        };
+        let Some(mut rendered) = rendered else {
+            continue;
+        };
+        // Everything after the footnotes heading is footnotes. A block that isn't a
+        // footnote itself is the rest of the footnote before it.
+        if in_footnotes && !is_footnote(block) && previous.is_some() {
+            rendered = format!("    {}", indent(rendered.as_str(), "    "));
+        }
+        if let Some(previous) = previous {
+            let together = std::mem::discriminant(previous) == std::mem::discriminant(&block.block_type)
+                && matches!(
+                    block.block_type,
+                    BlockType::BulletedListItem { .. } | BlockType::NumberedListItem { .. } | BlockType::ToDo { .. }
+                );
+            output.push_str(if together { "\n" } else { "\n\n" });
+        }
+        output.push_str(rendered.as_str());
+        previous = Some(&block.block_type);
+    }
+    output
+}
+
+fn render_block(block: &Block, block_type: &BlockType, number: usize) -> Option<String> {
+    let rendered = match block_type {
+        BlockType::Paragraph { paragraph } => {
+            let text = render_rich_text(&paragraph.rich_text);
+            // Markdown has no empty paragraphs, and no way to nest blocks under one.
+            let children = paragraph.children.as_deref().map(render_blocks).unwrap_or_default();
+            [text, children]
+                .into_iter()
+                .filter(|xs| !xs.is_empty())
+                .collect::<Vec<_>>()
+                .join("\n\n")
+        }
+        BlockType::Heading1 { heading_1 } => render_heading(1, heading_1),
+        BlockType::Heading2 { heading_2 } => render_heading(2, heading_2),
+        BlockType::Heading3 { heading_3 } => render_heading(3, heading_3),
+        BlockType::BulletedListItem { bulleted_list_item } => render_list_item(
+            "- ",
+            &bulleted_list_item.rich_text,
+            bulleted_list_item.children.as_deref(),
+        ),
+        BlockType::NumberedListItem { numbered_list_item } => render_list_item(
+            format!("{number}. ").as_str(),
+            &numbered_list_item.rich_text,
+            numbered_list_item.children.as_deref(),
+        ),
+        BlockType::ToDo { to_do } => {
+            let marker = if to_do.checked == Some(true) {
+                "- [x] "
+            } else {
+                "- [ ] "
+            };
+            render_list_item(marker, &to_do.rich_text, to_do.children.as_deref())
+        }
+        BlockType::Quote { quote } => {
+            render_quote(render_rich_text(&quote.rich_text).as_str(), quote.children.as_deref())
+        }
+        BlockType::Callout { callout } => render_callout(callout),
+        BlockType::Toggle { toggle } => render_toggle(&toggle.rich_text, toggle.children.as_deref()),
+        BlockType::Code { code } => render_code(code),
+        BlockType::Equation { equation } => format!("$$\n{}\n$$", equation.expression),
+        // `---` at the top of a page would open front matter.
+        BlockType::Divider { .. } => "***".to_string(),
+        BlockType::Table { table } => render_table(table)?,
+        BlockType::Bookmark { bookmark } => render_url(bookmark.url.as_str(), &bookmark.caption),
+        BlockType::Embed { embed } => render_url(embed.url.as_str(), &[]),
+        BlockType::LinkPreview { link_preview } => render_url(link_preview.url.as_str(), &[]),
+        BlockType::Video { video } => render_url(file_url(&video.file_type), &[]),
+        BlockType::Pdf { pdf } => render_url(file_url(&pdf.file_type), &pdf.caption),
+        BlockType::File { file } => {
+            let name = if file.caption.is_empty() {
+                escape(file.name.as_str(), false)
+            } else {
+                render_rich_text(&file.caption)
+            };
+            format!("[{name}]({})", link_destination(file_url(&file.file_type)))
+        }
+        BlockType::TableOfContents { .. } => "[TOC]".to_string(),
+        BlockType::ChildPage { child_page } => render_page_link(child_page.title.as_str(), block.id.as_deref()),
+        BlockType::ChildDatabase { child_database } => {
+            render_page_link(child_database.title.as_str(), block.id.as_deref())
+        }
+        BlockType::LinkToPage { link_to_page } => {
+            let id = match link_to_page {
+                Parent::PageId { page_id } => page_id.as_str(),
+                Parent::DatabaseId { database_id } => database_id.as_str(),
+                Parent::BlockId { block_id } => block_id.as_str(),
+                _ => return None,
+            };
+            format!("<{}>", notion_url(id))
+        }
+        BlockType::SyncedBlock { synced_block } => render_blocks(synced_block.children.as_deref().unwrap_or_default()),
+        BlockType::Template { template } => {
+            let text = render_rich_text(&template.rich_text);
+            let children = template.children.as_deref().map(render_blocks).unwrap_or_default();
+            [text, children]
+                .into_iter()
+                .filter(|xs| !xs.is_empty())
+                .collect::<Vec<_>>()
+                .join("\n\n")
+        }
+        BlockType::Image { image } => render_image(image, ""),
+        BlockType::TableRow { .. }
+        | BlockType::Breadcrumb { .. }
+        | BlockType::ColumnList { .. }
+        | BlockType::Column { .. }
+        | BlockType::None => return None,
+    };
+    Some(rendered).filter(|xs| !xs.is_empty())
+}
+
+/// Headings can't hold anything in Markdown. The client's heading type has no children,
+/// so a toggle heading's contents never reach us and only its text is rendered.
+fn render_heading(level: usize, heading: &HeadingsValue) -> String {
+    let mut text = render_rich_text(&heading.rich_text).replace('\n', " ");
+    // A heading that ends in `#` would lose it as a closing sequence.
+    if text.ends_with('#') && !text.ends_with("\\#") {
+        text.insert(text.len() - 1, '\\');
+    }
+    format!("{} {text}", "#".repeat(level))
+}
+
+/// Continuation lines line up with the text after the marker. A task's checkbox is part
+/// of its text, so its continuation lines line up with the checkbox.
+fn render_list_item(marker: &str, rich_text: &[RichText], children: Option<&[Block]>) -> String {
+    let text = render_rich_text(rich_text);
+    let pad = " ".repeat(marker.trim_end_matches("[ ] ").trim_end_matches("[x] ").len());
+    let mut item = format!("{marker}{}", indent(text.as_str(), pad.as_str()));
+    let children = children.unwrap_or_default();
+    if !children.is_empty() {
+        // Anything but a nested list needs a blank line, or it joins the item's text.
+        let nested_list = matches!(
+            children[0].block_type,
+            BlockType::BulletedListItem { .. } | BlockType::NumberedListItem { .. } | BlockType::ToDo { .. }
+        );
+        item.push_str(if nested_list { "\n" } else { "\n\n" });
+        item.push_str(format!("{pad}{}", indent(render_blocks(children).as_str(), pad.as_str())).as_str());
+    }
+    item.trim_end().to_string()
+}
+
+fn render_quote(text: &str, children: Option<&[Block]>) -> String {
+    let mut body = text.to_string();
+    let children = children.map(render_blocks).unwrap_or_default();
+    if !children.is_empty() {
+        if !body.is_empty() {
+            body.push_str("\n\n");
+        }
+        body.push_str(children.as_str());
+    }
+    quoted(body.as_str())
+}
+
+/// Admonitions go back to `> [!NOTE]`, and footnotes to `[^label]:`. Any other callout
+/// is a quote that starts with its emoji.
+fn render_callout(callout: &CalloutValue) -> String {
+    let emoji = match callout.icon {
+        Icon::Emoji(ref emoji) => emoji.emoji.as_str(),
+        Icon::File(_) => "",
+    };
+    if let Some((label, rest)) = footnote_label(emoji, &callout.rich_text) {
+        let text = render_rich_text(rest);
+        return format!("[^{label}]: {}", indent(text.as_str(), "    "));
+    }
+    if let Some(kind) = Admonition::from_emoji(emoji) {
+        let only_label = matches!(
+            callout.rich_text.as_slice(),
+            [RichText::Text { text, annotations: Some(annotations), .. }]
+                if annotations.bold && text.content == kind.label()
+        );
+        let mut body = format!("[!{}]", kind.marker());
+        if !only_label {
+            body.push('\n');
+            body.push_str(render_rich_text(&callout.rich_text).as_str());
+        }
+        return quoted(body.as_str());
+    }
+    let text = render_rich_text(&callout.rich_text);
+    quoted(format!("{emoji} {text}").trim())
+}
+
+/// The `[label] ` that starts a footnote's text, and the text after it.
+fn footnote_label<'a>(emoji: &str, rich_text: &'a [RichText]) -> Option<(&'a str, &'a [RichText])> {
+    if emoji != crate::FOOTNOTE_EMOJI {
+        return None;
+    }
+    let (RichText::Text { text, .. }, rest) = rich_text.split_first()? else {
+        return None;
+    };
+    let label = text.content.strip_prefix('[')?.strip_suffix("] ")?;
+    Some((label, rest))
+}
+
+fn is_footnote(block: &Block) -> bool {
+    match &block.block_type {
+        BlockType::Callout { callout } => match callout.icon {
+            Icon::Emoji(ref emoji) => footnote_label(emoji.emoji.as_str(), &callout.rich_text).is_some(),
+            Icon::File(_) => false,
+        },
+        _ => false,
+    }
+}
+
+fn is_footnotes_heading(heading: &HeadingsValue, next: Option<&Block>) -> bool {
+    plain_text(&heading.rich_text) == "Footnotes" && next.is_some_and(is_footnote)
+}
+
+/// `<details>` is the only Markdown that folds. Its summary is HTML, not Markdown.
+fn render_toggle(rich_text: &[RichText], children: Option<&[Block]>) -> String {
+    let summary = render_html(rich_text);
+    let children = children.map(render_blocks).unwrap_or_default();
+    if children.is_empty() {
+        format!("<details>\n<summary>{summary}</summary>\n</details>")
+    } else {
+        format!("<details>\n<summary>{summary}</summary>\n\n{children}\n\n</details>")
+    }
+}
+
+fn render_code(code: &CodeValue) -> String {
+    let content = plain_text(&code.rich_text);
+    let mut info = languages::info_string_for(&code.language).unwrap_or_default();
+    // Info strings take backslash escapes.
+    let caption = plain_text(&code.caption).replace('\\', "\\\\");
+    if !caption.is_empty() {
+        if info.is_empty() {
+            info = "text".to_string();
+        }
+        let quote = if caption.contains('"') { '\'' } else { '"' };
+        info.push_str(format!(" title={quote}{caption}{quote}").as_str());
+    }
+    // A backtick fence can't have backticks after it, so those get a tilde fence.
+    let mark = if info.contains('`') { '~' } else { '`' };
+    let fence = mark
+        .to_string()
+        .repeat(std::cmp::max(3, longest_run(content.as_str(), mark) + 1));
+    format!("{fence}{info}\n{content}\n{fence}")
+}
+
+/// GFM tables need a header, so a table without one uses its first row.
+fn render_table(table: &TableValue) -> Option<String> {
+    let rows: Vec<&TableRowsValue> = table
+        .children
+        .as_deref()
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|xs| match &xs.block_type {
+            BlockType::TableRow { table_row } => Some(table_row),
+            _ => None,
+        })
+        .collect();
+    let width = table.table_width as usize;
+    let line = |cells: Vec<String>| format!("| {} |", cells.join(" | "));
+    let row = |row: &TableRowsValue| {
+        let mut cells: Vec<String> = row
+            .cells
+            .iter()
+            .map(|cell| escape_pipes(render_rich_text(cell).replace('\n', "<br>").as_str()))
+            .collect();
+        cells.resize(width, String::new());
+        line(cells)
+    };
+    let (header, body) = rows.split_first()?;
+    let mut lines = vec![row(header), line(vec!["---".to_string(); width])];
+    lines.extend(body.iter().map(|xs| row(xs)));
+    Some(lines.join("\n"))
+}
+
+/// Plain text already has its pipes escaped, but a pipe in code would still end the cell.
+fn escape_pipes(cell: &str) -> String {
+    let mut output = String::with_capacity(cell.len());
+    let mut previous = ' ';
+    for c in cell.chars() {
+        if c == '|' && previous != '\\' {
+            output.push('\\');
+        }
+        output.push(c);
+        previous = c;
+    }
+    output
+}
+
+fn render_image(image: &ImageValue, alt: &str) -> String {
+    format!(
+        "![{}]({})",
+        escape(alt, false),
+        link_destination(file_url(&image.file_type))
+    )
+}
+
+/// `convert` puts an image's alt text in a gray, italic paragraph right after it.
+fn image_caption(block: &Block) -> Option<String> {
+    let BlockType::Paragraph { paragraph } = &block.block_type else {
+        return None;
+    };
+    let is_caption = !paragraph.rich_text.is_empty()
+        && paragraph.rich_text.iter().all(|xs| match xs {
+            RichText::Text {
+                annotations: Some(annotations),
+                text,
+                ..
+            } => annotations.italic && annotations.color == rich_text::TextColor::Gray && text.link.is_none(),
+            _ => false,
+        });
+    is_caption.then(|| plain_text(&paragraph.rich_text))
+}
+
+/// A URL on its own line, which `convert` turns back into the same kind of block.
+fn render_url(url: &str, caption: &[RichText]) -> String {
+    if caption.is_empty() {
+        format!("<{}>", url.replace('>', "%3E"))
+    } else {
+        format!("[{}]({})", render_rich_text(caption), link_destination(url))
+    }
+}
+
+fn render_page_link(title: &str, id: Option<&str>) -> String {
+    match id {
+        Some(id) => format!("[{}]({})", escape(title, false), notion_url(id)),
+        None => escape(title, true),
+    }
+}
+
+fn notion_url(id: &str) -> String {
+    format!("https://www.notion.so/{}", id.replace('-', ""))
+}
+
+fn file_url(file: &File) -> &str {
+    match file {
+        File::External { external } => external.url.as_str(),
+        File::File { file } => file.url.as_str(),
+    }
+}
+
+/// A link destination, in angle brackets if it has anything that would end it early.
+fn link_destination(url: &str) -> String {
+    let url = url.replace('\\', "\\\\");
+    if url.contains([' ', '(', ')', '<', '>']) {
+        format!("<{}>", url.replace('<', "%3C").replace('>', "%3E"))
+    } else {
+        url
+    }
+}
+
+/// Styles Markdown can express, in the order they nest, outermost first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mark {
+    Strikethrough,
+    Bold,
+    Italic,
+    Underline,
+    Highlight,
+}
+
+impl Mark {
+    const ALL: [Mark; 5] = [
+        Mark::Strikethrough,
+        Mark::Bold,
+        Mark::Italic,
+        Mark::Underline,
+        Mark::Highlight,
+    ];
+
+    fn applies(&self, annotations: &Annotations) -> bool {
+        match self {
+            Mark::Strikethrough => annotations.strikethrough,
+            Mark::Bold => annotations.bold,
+            Mark::Italic => annotations.italic,
+            Mark::Underline => annotations.underline,
+            Mark::Highlight => annotations.color == rich_text::TextColor::YellowBackground,
+        }
+    }
+
+    fn open(&self) -> &'static str {
+        match self {
+            Mark::Strikethrough => "~~",
+            Mark::Bold => "**",
+            Mark::Italic => "*",
+            Mark::Underline => "<u>",
+            Mark::Highlight => "<mark>",
+        }
+    }
+
+    fn close(&self) -> &'static str {
+        match self {
+            Mark::Strikethrough => "~~",
+            Mark::Bold => "**",
+            Mark::Italic => "*",
+            Mark::Underline => "</u>",
+            Mark::Highlight => "</mark>",
+        }
+    }
+}
+
+/// A run of rich text, ready to be wrapped in its styles.
+struct Piece {
+    markdown: String,
+    marks: Vec<Mark>,
+    link: Option<String>,
+}
+
+/// Something open while rendering rich text: a style, or the text of a link.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Open<'a> {
+    Mark(Mark),
+    Link(&'a str),
+}
+
+/// Rich text as inline Markdown. Runs that share a link share one `[…](…)`, and styles
+/// that carry on from one run to the next stay open, so `**bold _and italic_**` comes
+/// back the way it went in.
+fn render_rich_text(rich_text: &[RichText]) -> String {
+    let mut line_start = true;
+    let mut pieces: Vec<Piece> = Vec::new();
+    for run in rich_text {
+        pieces.extend(piece(run, line_start));
+        match run {
+            RichText::Text { text, .. } if text.content.is_empty() => {}
+            RichText::Text { text, .. } => line_start = text.content.ends_with('\n'),
+            RichText::None => {}
+            _ => line_start = false,
+        }
+    }
+    let mut output = render_styled(&pieces);
+    // Whitespace at the end of a block is lost anyway, and before a line break it's a hard break.
+    output.truncate(output.trim_end().len());
+    output
+}
+
+fn piece(run: &RichText, line_start: bool) -> Option<Piece> {
+    let (markdown, annotations, link) = match run {
+        RichText::Text {
+            text,
+            annotations,
+            href,
+            ..
+        } => {
+            let annotations = annotations.clone().unwrap_or_default();
+            let link = text.link.as_ref().map(|xs| xs.url.clone()).or(href.clone());
+            // A reference to a footnote: gray `[label]` pointing at the footnote.
+            if let Some(label) = link
+                .as_deref()
+                .filter(|xs| xs.starts_with("#fn-"))
+                .and_then(|_| text.content.strip_prefix('['))
+                .and_then(|xs| xs.strip_suffix(']'))
+            {
+                return Some(Piece {
+                    markdown: format!("[^{label}]"),
+                    marks: Vec::new(),
+                    link: None,
+                });
+            }
+            let markdown = if annotations.code {
+                code_span(text.content.as_str())
+            } else {
+                escape(text.content.as_str(), line_start)
+            };
+            (markdown, annotations, link)
+        }
+        RichText::Equation {
+            equation, annotations, ..
+        } => (format!("${}$", equation.expression), annotations.clone(), None),
+        RichText::Mention {
+            mention,
+            annotations,
+            plain_text,
+            href,
+        } => {
+            let link = match mention {
+                Mention::Page { page } => Some(notion_url(page.id.as_str())),
+                Mention::Database { database } => Some(notion_url(database.id.as_str())),
+                Mention::LinkPreview { link_preview } => Some(link_preview.url.clone()),
+                _ => href.clone(),
+            };
+            (escape(plain_text.as_str(), line_start), annotations.clone(), link)
+        }
+        RichText::None => return None,
+    };
+    let marks = Mark::ALL.into_iter().filter(|xs| xs.applies(&annotations)).collect();
+    Some(Piece { markdown, marks, link })
+}
+
+/// Open and close styles and links as they change from run to run. Links go inside
+/// styles, so a style can carry on across one. Emphasis can't start just before
+/// whitespace or end just after it, so whitespace at a style boundary moves outside.
+fn render_styled(pieces: &[Piece]) -> String {
+    let mut output = String::new();
+    let mut open: Vec<Open> = Vec::new();
+    for (idx, piece) in pieces.iter().enumerate() {
+        let wanted = |xs: &Open| match xs {
+            Open::Mark(mark) => piece.marks.contains(mark),
+            Open::Link(url) => piece.link.as_deref() == Some(*url),
+        };
+        let text = piece.markdown.trim_start();
+        // Bold and italic don't show on whitespace, so whitespace alone doesn't close them.
+        let invisible = |xs: &Open| wanted(xs) || matches!(xs, Open::Mark(Mark::Bold | Mark::Italic));
+        let same_link = piece.link.as_deref().is_none_or(|url| open.contains(&Open::Link(url)));
+        if text.is_empty() && same_link && open.iter().all(invisible) {
+            output.push_str(piece.markdown.as_str());
+            continue;
+        }
+        // Close from the inside out until everything still open is wanted here.
+        if let Some(keep) = open.iter().position(|xs| !wanted(xs)) {
+            open.drain(keep..).rev().for_each(|xs| close(&mut output, xs));
+        }
+        output.push_str(&piece.markdown[..piece.markdown.len() - text.len()]);
+        if text.is_empty() {
+            continue;
+        }
+        // Styles that last longer go outside, so they don't have to close early.
+        let mut opening: Vec<Mark> = piece
+            .marks
+            .iter()
+            .copied()
+            .filter(|mark| !open.contains(&Open::Mark(*mark)))
+            .collect();
+        opening.sort_by_key(|mark| std::cmp::Reverse(lasts(&pieces[idx..], *mark)));
+        for mark in opening {
+            output.push_str(mark.open());
+            open.push(Open::Mark(mark));
+        }
+        if let Some(url) = piece.link.as_deref().filter(|url| !open.contains(&Open::Link(url))) {
+            output.push('[');
+            open.push(Open::Link(url));
+        }
+        output.push_str(text);
+    }
+    open.into_iter().rev().for_each(|xs| close(&mut output, xs));
+    output
+}
+
+/// How many pieces in a row keep a style, counting whitespace that doesn't show it.
+fn lasts(pieces: &[Piece], mark: Mark) -> usize {
+    pieces
+        .iter()
+        .take_while(|xs| {
+            xs.marks.contains(&mark) || (xs.markdown.trim().is_empty() && matches!(mark, Mark::Bold | Mark::Italic))
+        })
+        .count()
+}
+
+/// Close a style outside any whitespace it ends with. A link keeps its whitespace.
+fn close(output: &mut String, open: Open) {
+    match open {
+        Open::Mark(mark) => {
+            let trailing = output.len() - output.trim_end().len();
+            let spaces = output.split_off(output.len() - trailing);
+            output.push_str(mark.close());
+            output.push_str(spaces.as_str());
+        }
+        Open::Link(url) => output.push_str(format!("]({})", link_destination(url)).as_str()),
+    }
+}
+
+/// Rich text as inline HTML, for the places Markdown isn't parsed, like `<summary>`.
+fn render_html(rich_text: &[RichText]) -> String {
+    rich_text
+        .iter()
+        .map(|run| {
+            let (content, annotations, link) = match run {
+                RichText::Text {
+                    text,
+                    annotations,
+                    href,
+                    ..
+                } => (
+                    text.content.clone(),
+                    annotations.clone().unwrap_or_default(),
+                    text.link.as_ref().map(|xs| xs.url.clone()).or(href.clone()),
+                ),
+                RichText::Equation {
+                    equation, annotations, ..
+                } => (equation.expression.clone(), annotations.clone(), None),
+                RichText::Mention {
+                    plain_text,
+                    annotations,
+                    href,
+                    ..
+                } => (plain_text.clone(), annotations.clone(), href.clone()),
+                RichText::None => return String::new(),
+            };
+            let mut html = content.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;");
+            let tags = [
+                (annotations.code, "code"),
+                (annotations.underline, "u"),
+                (annotations.italic, "i"),
+                (annotations.bold, "b"),
+                (annotations.strikethrough, "s"),
+                (annotations.color == rich_text::TextColor::YellowBackground, "mark"),
+            ];
+            for (_, tag) in tags.iter().filter(|(on, _)| *on) {
+                html = format!("<{tag}>{html}</{tag}>");
+            }
+            match link {
+                Some(url) => format!("<a href=\"{}\">{html}</a>", url.replace('"', "&quot;")),
+                None => html,
+            }
+        })
+        .collect()
+}
+
+/// Backticks around code, enough of them that none inside end it early.
+fn code_span(content: &str) -> String {
+    let fence = "`".repeat(longest_run(content, '`') + 1);
+    let pad = if content.starts_with('`') || content.ends_with('`') {
+        " "
+    } else {
+        ""
+    };
+    format!("{fence}{pad}{content}{pad}{fence}")
+}
+
+fn longest_run(input: &str, wanted: char) -> usize {
+    let mut longest = 0;
+    let mut current = 0;
+    for c in input.chars() {
+        current = if c == wanted { current + 1 } else { 0 };
+        longest = std::cmp::max(longest, current);
+    }
+    longest
+}
+
+/// Escape anything in plain text that Markdown would read as markup, including the
+/// bare URLs and email addresses GFM would otherwise turn into links. `line_start` says
+/// whether the text begins a line of the block it's in.
+fn escape(input: &str, line_start: bool) -> String {
+    input
+        .split('\n')
+        .enumerate()
+        .map(|(idx, line)| {
+            let line = escape_inline(line);
+            if idx > 0 || line_start {
+                escape_line_start(line.as_str())
+            } else {
+                line
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn escape_inline(input: &str) -> String {
+    let chars: Vec<char> = input.chars().collect();
+    let mut output = String::with_capacity(input.len());
+    for (idx, c) in chars.iter().enumerate() {
+        let next = chars.get(idx + 1).copied().unwrap_or(' ');
+        let previous = idx.checked_sub(1).map_or(' ', |prev| chars[prev]);
+        let escaped = match c {
+            '\\' | '`' | '*' | '_' | '[' | ']' | '<' | '~' | '|' | '$' => true,
+            '&' => next.is_ascii_alphanumeric() || next == '#',
+            '@' => previous.is_alphanumeric() && next.is_alphanumeric(),
+            ':' => chars[idx + 1..].starts_with(&['/', '/']),
+            '.' => {
+                idx >= 3
+                    && chars[idx - 3..idx]
+                        .iter()
+                        .collect::<String>()
+                        .eq_ignore_ascii_case("www")
+            }
+            _ => false,
+        };
+        if escaped {
+            output.push('\\');
+        }
+        output.push(*c);
+    }
+    output
+}
+
+/// Escape whatever would make a line of text into a heading, quote, list item, or code
+/// block when it's at the start of a line.
+fn escape_line_start(line: &str) -> String {
+    let digits = line.len() - line.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    let rest = &line[digits..];
+    if digits > 0 && (rest.starts_with(". ") || rest.starts_with(") ") || rest == "." || rest == ")") {
+        format!("{}\\{rest}", &line[..digits])
+    } else if line.starts_with(['#', '>', '-', '+', '=']) {
+        format!("\\{line}")
+    } else if let Some(rest) = line.strip_prefix(' ') {
+        format!("&#32;{rest}")
+    } else if let Some(rest) = line.strip_prefix('\t') {
+        format!("&#9;{rest}")
+    } else {
+        line.to_string()
+    }
+}
+
+fn plain_text(rich_text: &[RichText]) -> String {
+    rich_text.iter().filter_map(|xs| xs.plain_text()).collect()
+}
+
+/// Indent every line after the first. Blank lines stay blank.
+fn indent(text: &str, prefix: &str) -> String {
+    text.split('\n')
+        .enumerate()
+        .map(|(idx, line)| {
+            if idx == 0 || line.is_empty() {
+                line.to_string()
+            } else {
+                format!("{prefix}{line}")
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn quoted(text: &str) -> String {
+    text.split('\n')
+        .map(|line| {
+            if line.is_empty() {
+                ">".to_string()
+            } else {
+                format!("> {line}")
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
@@ -0,0 +1,446 @@
+//! An in-memory Notion, for testing page creation and migration without a network or
+//! an API key. It holds pages and blocks the way Notion would, and turns down requests
+//! that break the limits Notion enforces: 100 blocks in any one list of children, 1000
+//! blocks and 500KB in a request, two levels of nesting below the blocks being
+//! appended, 100 runs in a piece of rich text, and 2000 characters in a run. It can
+//! also be told to fail requests, to exercise the retries.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::{Mutex, MutexGuard};
+
+use notion_client::endpoints::blocks::append::request::AppendBlockChildrenRequest;
+use notion_client::endpoints::blocks::update::request::UpdateABlockRequest;
+use notion_client::endpoints::pages::create::request::CreateAPageRequest;
+use notion_client::objects::block::Block;
+use notion_client::objects::page::Page as NotionPage;
+use notion_client::objects::parent::Parent;
+
+use crate::{block_children, block_children_mut, block_children_slot, ApiError, NotionApi, PlannedRequest};
+
+/// The most blocks Notion takes in one list of children.
+const CHILDREN_LIMIT: usize = 100;
+
+/// How deep children can go below the blocks in an append request.
+const NESTING_LIMIT: usize = 2;
+
+/// The most characters Notion takes in a single text run.
+const TEXT_LIMIT: usize = 2000;
+
+/// The most text runs Notion takes in a single piece of rich text.
+const RUNS_LIMIT: usize = 100;
+
+/// The most blocks Notion takes in one request, counting nested ones.
+const BLOCKS_LIMIT: usize = 1000;
+
+/// The biggest request body Notion takes, in bytes.
+const PAYLOAD_LIMIT: usize = 500_000;
+
+#[derive(Debug, Default)]
+pub struct FakeNotion {
+    state: Mutex<FakeState>,
+}
+
+#[derive(Debug, Default)]
+struct FakeState {
+    /// How many ids we've handed out.
+    made: usize,
+    pages: HashMap<String, NotionPage>,
+    /// Every block we hold, without its children.
+    blocks: HashMap<String, Block>,
+    /// The ids of the children of each page or block, in order.
+    children: HashMap<String, Vec<String>>,
+    /// Statuses to fail the next requests with, one per request.
+    failures: VecDeque<u16>,
+    /// Every request that changed something or tried to, in order.
+    requests: Vec<PlannedRequest>,
+}
+
+impl FakeNotion {
+    /// Fail the next request with this HTTP status, like 409 or 429. Call it more than
+    /// once to fail more than one request.
+    pub fn fail_next(&self, status: u16) {
+        self.state().failures.push_back(status);
+    }
+
+    /// Every create, append, and update request made so far, including the ones that
+    /// failed.
+    pub fn requests(&self) -> Vec<PlannedRequest> {
+        self.state().requests.clone()
+    }
+
+    /// The children of a page or block, with all their children filled in.
+    pub fn children(&self, parent_id: &str) -> Vec<Block> {
+        self.state().tree(parent_id)
+    }
+
+    fn state(&self) -> MutexGuard<'_, FakeState> {
+        // A panic in some other test's request doesn't make our data any less good.
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl FakeState {
+    fn next_id(&mut self) -> String {
+        self.made += 1;
+        format!("{:08x}-0000-4000-8000-{:012x}", self.made, self.made)
+    }
+
+    fn injected_failure(&mut self) -> Result<(), ApiError> {
+        match self.failures.pop_front() {
+            Some(status) => {
+                let code = match status {
+                    409 => "conflict_error",
+                    429 => "rate_limited",
+                    500.. => "internal_server_error",
+                    _ => "validation_error",
+                };
+                Err(api_error(status, code, "failure injected by the test"))
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn exists(&self, id: &str) -> bool {
+        self.pages.contains_key(id) || self.blocks.contains_key(id)
+    }
+
+    /// Store blocks under a parent, after the given child or at the end, and return the
+    /// top-level ones as Notion would.
+    fn insert(&mut self, parent_id: &str, blocks: Vec<Block>, after: Option<&str>) -> Vec<Block> {
+        if blocks.is_empty() {
+            return Vec::new();
+        }
+        let parent = if self.pages.contains_key(parent_id) {
+            Parent::PageId {
+                page_id: parent_id.to_owned(),
+            }
+        } else {
+            Parent::BlockId {
+                block_id: parent_id.to_owned(),
+            }
+        };
+        let mut created = Vec::with_capacity(blocks.len());
+        let mut ids = Vec::with_capacity(blocks.len());
+        for mut block in blocks {
+            let id = self.next_id();
+            let children = block_children_mut(&mut block).map(std::mem::take).unwrap_or_default();
+            block.id = Some(id.clone());
+            block.parent = Some(parent.clone());
+            block.object = Some("block".to_string());
+            block.has_children = Some(!children.is_empty());
+            self.insert(id.as_str(), children, None);
+            self.blocks.insert(id.clone(), block.clone());
+            ids.push(id);
+            created.push(block);
+        }
+        let siblings = self.children.entry(parent_id.to_owned()).or_default();
+        let at = after
+            .and_then(|after| siblings.iter().position(|xs| xs == after))
+            .map_or(siblings.len(), |idx| idx + 1);
+        siblings.splice(at..at, ids);
+        if let Some(block) = self.blocks.get_mut(parent_id) {
+            block.has_children = Some(true);
+        }
+        created
+    }
+
+    /// Blocks whose notion-client type has no room for children, like callouts, have
+    /// theirs follow them instead, the way `convert` shows them.
+    fn tree(&self, parent_id: &str) -> Vec<Block> {
+        let Some(ids) = self.children.get(parent_id) else {
+            return Vec::new();
+        };
+        let mut blocks = Vec::with_capacity(ids.len());
+        for id in ids {
+            let Some(mut block) = self.blocks.get(id).cloned() else {
+                continue;
+            };
+            let children = self.tree(id);
+            match block_children_slot(&mut block) {
+                Some(slot) if !children.is_empty() => {
+                    *slot = Some(children);
+                    blocks.push(block);
+                }
+                Some(_) => blocks.push(block),
+                None => {
+                    blocks.push(block);
+                    blocks.extend(children);
+                }
+            }
+        }
+        blocks
+    }
+}
+
+impl NotionApi for FakeNotion {
+    async fn create_page(&self, request: CreateAPageRequest) -> Result<NotionPage, ApiError> {
+        let mut state = self.state();
+        state.requests.push(PlannedRequest::CreatePage);
+        state.injected_failure()?;
+        check_payload(&request)?;
+        // Only pages in a database have properties besides their title.
+        let extra = request.properties.keys().find(|key| *key != "title");
+        if let (Parent::PageId { .. }, Some(key)) = (&request.parent, extra) {
+            let message = format!("{key} is not a property that exists on a page under a page");
+            return Err(api_error(400, "validation_error", message.as_str()));
+        }
+        let children = request.children.unwrap_or_default();
+        check_children(&children, 0)?;
+        let id = state.next_id();
+        let page = page_object(id.as_str(), request.parent, request.properties)?;
+        state.pages.insert(id.clone(), page.clone());
+        state.insert(id.as_str(), children, None);
+        Ok(page)
+    }
+
+    async fn append_children(
+        &self,
+        parent_id: &str,
+        request: AppendBlockChildrenRequest,
+    ) -> Result<Vec<Block>, ApiError> {
+        let mut state = self.state();
+        state.requests.push(PlannedRequest::Append {
+            parent: parent_id.to_owned(),
+            blocks: request.children.len(),
+            total: request.children.iter().map(crate::count_blocks).sum(),
+        });
+        state.injected_failure()?;
+        if !state.exists(parent_id) {
+            return Err(not_found(parent_id));
+        }
+        if let Some(ref after) = request.after {
+            let siblings = state.children.get(parent_id).map(Vec::as_slice).unwrap_or_default();
+            if !siblings.contains(after) {
+                let message = format!("Block {after} is not a child of {parent_id}.");
+                return Err(api_error(400, "validation_error", message.as_str()));
+            }
+        }
+        check_payload(&request)?;
+        if request.children.iter().map(crate::count_blocks).sum::<usize>() > BLOCKS_LIMIT {
+            let message = format!("body.children should have at most {BLOCKS_LIMIT} blocks in all.");
+            return Err(api_error(400, "validation_error", message.as_str()));
+        }
+        check_children(&request.children, 0)?;
+        Ok(state.insert(parent_id, request.children, request.after.as_deref()))
+    }
+
+    async fn update_block(&self, block_id: &str, request: UpdateABlockRequest) -> Result<Option<Block>, ApiError> {
+        let mut state = self.state();
+        state.requests.push(PlannedRequest::Update {
+            block: block_id.to_owned(),
+        });
+        state.injected_failure()?;
+        let Some(existing) = state.blocks.get(block_id).cloned() else {
+            return Err(not_found(block_id));
+        };
+        let Some(update) = request.block else {
+            return Ok(Some(existing));
+        };
+        check_text(&update)?;
+        let updated = Block {
+            block_type: update.block_type,
+            ..existing
+        };
+        state.blocks.insert(block_id.to_owned(), updated.clone());
+        Ok(Some(updated))
+    }
+
+    async fn retrieve_page(&self, page_id: &str) -> Result<NotionPage, ApiError> {
+        let mut state = self.state();
+        state.injected_failure()?;
+        state.pages.get(page_id).cloned().ok_or_else(|| not_found(page_id))
+    }
+}
+
+/// Turn down a list of children that breaks any of Notion's limits.
+fn check_children(blocks: &[Block], depth: usize) -> Result<(), ApiError> {
+    if blocks.len() > CHILDREN_LIMIT {
+        let message = format!(
+            "body.children.length should be ≤ `{CHILDREN_LIMIT}`, instead was `{}`.",
+            blocks.len()
+        );
+        return Err(api_error(400, "validation_error", message.as_str()));
+    }
+    for block in blocks {
+        check_text(block)?;
+        let Some(children) = block_children(block).filter(|xs| !xs.is_empty()) else {
+            continue;
+        };
+        if depth == NESTING_LIMIT {
+            let message = format!("body.children should be nested at most {NESTING_LIMIT} levels deep.");
+            return Err(api_error(400, "validation_error", message.as_str()));
+        }
+        check_children(children, depth + 1)?;
+    }
+    Ok(())
+}
+
+/// Turn down request bodies that are too big.
+fn check_payload(request: &impl serde::Serialize) -> Result<(), ApiError> {
+    let size = serde_json::to_vec(request).map_or(0, |xs| xs.len());
+    if size > PAYLOAD_LIMIT {
+        let message = format!("Request body too large: {size} bytes, and the limit is {PAYLOAD_LIMIT}.");
+        return Err(api_error(400, "validation_error", message.as_str()));
+    }
+    Ok(())
+}
+
+/// Turn down text runs that are too long, and rich text with too many runs, wherever
+/// they are in the block.
+fn check_text(block: &Block) -> Result<(), ApiError> {
+    /// Without its children, every array in a block we make is rich text, or a table
+    /// row's cells, which are few.
+    fn most_runs(value: &serde_json::Value) -> usize {
+        match value {
+            serde_json::Value::Object(map) => map.values().map(most_runs).max().unwrap_or_default(),
+            serde_json::Value::Array(items) => items.iter().map(most_runs).fold(items.len(), std::cmp::max),
+            _ => 0,
+        }
+    }
+    fn longest(value: &serde_json::Value) -> usize {
+        match value {
+            serde_json::Value::Object(map) => {
+                let own = map
+                    .get("text")
+                    .and_then(|text| text.get("content"))
+                    .and_then(|content| content.as_str())
+                    .map_or(0, |content| content.chars().count());
+                map.values().map(longest).fold(own, std::cmp::max)
+            }
+            serde_json::Value::Array(items) => items.iter().map(longest).max().unwrap_or_default(),
+            _ => 0,
+        }
+    }
+    // Children are checked on their own.
+    let mut block = block.clone();
+    block_children_mut(&mut block).map(std::mem::take);
+    let value = serde_json::to_value(&block).unwrap_or_default();
+    let length = longest(&value);
+    if length > TEXT_LIMIT {
+        let message = format!("body.rich_text.text.content.length should be ≤ `{TEXT_LIMIT}`, instead was `{length}`.");
+        return Err(api_error(400, "validation_error", message.as_str()));
+    }
+    let runs = most_runs(&value);
+    if runs > RUNS_LIMIT {
+        let message = format!("body.rich_text.length should be ≤ `{RUNS_LIMIT}`, instead was `{runs}`.");
+        return Err(api_error(400, "validation_error", message.as_str()));
+    }
+    Ok(())
+}
+
+pub(crate) fn page_object(
+    id: &str,
+    parent: Parent,
+    properties: BTreeMap<String, notion_client::objects::page::PageProperty>,
+) -> Result<NotionPage, ApiError> {
+    let now = "2024-07-30T00:00:00.000Z";
+    let user = serde_json::json!({ "object": "user", "id": "00000000-0000-4000-8000-000000000000" });
+    let page = serde_json::json!({
+        "id": id,
+        "created_time": now,
+        "created_by": user,
+        "last_edited_time": now,
+        "last_edited_by": user,
+        "archived": false,
+        "properties": properties,
+        "parent": parent,
+        "url": format!("https://www.notion.so/{}", id.replace('-', "")),
+    });
+    serde_json::from_value(page).map_err(|e| ApiError::Invalid(e.to_string()))
+}
+
+fn not_found(id: &str) -> ApiError {
+    let message = format!("Could not find block with ID: {id}.");
+    api_error(404, "object_not_found", message.as_str())
+}
+
+fn api_error(status: u16, code: &str, message: &str) -> ApiError {
+    ApiError::Status {
+        status,
+        code: code.to_string(),
+        message: message.to_string(),
+        retry_after: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::convert;
+
+    fn paragraphs(count: usize) -> Vec<Block> {
+        convert("words\n\n".repeat(count).as_str())
+    }
+
+    #[tokio::test]
+    async fn enforces_limits() {
+        let notion = FakeNotion::default();
+        let page = notion
+            .create_page(CreateAPageRequest::default())
+            .await
+            .expect("an empty page is fine");
+
+        let append = |children: Vec<Block>| AppendBlockChildrenRequest { children, after: None };
+        let too_many = notion.append_children(page.id.as_str(), append(paragraphs(101))).await;
+        assert!(matches!(too_many, Err(ref error) if error.status() == Some(400)));
+        let created = notion
+            .append_children(page.id.as_str(), append(paragraphs(100)))
+            .await
+            .expect("100 blocks is fine");
+        assert_eq!(created.len(), 100);
+
+        let nested = convert("- one\n  - two\n    - three\n");
+        assert!(notion.append_children(page.id.as_str(), append(nested)).await.is_ok());
+        let deeper = convert("- one\n  - two\n    - three\n      - four\n");
+        assert!(notion.append_children(page.id.as_str(), append(deeper)).await.is_err());
+
+        let long = convert("x".repeat(2001).as_str());
+        let mut block = long[0].clone();
+        if let notion_client::objects::block::BlockType::Paragraph { ref mut paragraph } = block.block_type {
+            paragraph.rich_text.truncate(1);
+            if let notion_client::objects::rich_text::RichText::Text { ref mut text, .. } = paragraph.rich_text[0] {
+                text.content = "x".repeat(2001);
+            }
+        }
+        assert!(notion
+            .append_children(page.id.as_str(), append(vec![block]))
+            .await
+            .is_err());
+        assert!(notion.append_children("nowhere", append(paragraphs(1))).await.is_err());
+
+        // One good append of 100 blocks and one of three nested ones.
+        let stored = notion.children(page.id.as_str());
+        assert_eq!(stored.len(), 101);
+        assert_eq!(notion.requests().len(), 7);
+    }
+
+    #[tokio::test]
+    async fn keeps_order_and_fails_on_request() {
+        let notion = FakeNotion::default();
+        let page = notion
+            .create_page(CreateAPageRequest {
+                children: Some(convert("first\n\nthird\n")),
+                ..Default::default()
+            })
+            .await
+            .expect("creating a page should work");
+        let first = notion.children(page.id.as_str())[0].id.clone();
+        notion.fail_next(409);
+        let request = AppendBlockChildrenRequest {
+            children: convert("second\n"),
+            after: first,
+        };
+        let conflict = notion.append_children(page.id.as_str(), request.clone()).await;
+        assert!(matches!(conflict, Err(ref error) if error.status() == Some(409)));
+        notion
+            .append_children(page.id.as_str(), request)
+            .await
+            .expect("the retry should work");
+        let texts: Vec<String> = notion
+            .children(page.id.as_str())
+            .iter()
+            .map(|xs| crate::export::blocks_to_markdown(std::slice::from_ref(xs)))
+            .collect();
+        assert_eq!(texts, vec!["first\n", "second\n", "third\n"]);
+    }
+}
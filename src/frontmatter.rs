@@ -0,0 +1,388 @@
+//! YAML or TOML front matter at the top of a Markdown file, turned into Notion page
+//! properties, an icon, and a cover image.
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use markdown::mdast::Node;
+use markdown::unist::Position;
+use notion_client::objects::emoji::Emoji;
+use notion_client::objects::file::{ExternalFile, File};
+use notion_client::objects::page::{DateOrDateTime, DatePropertyValue, Icon, PageProperty, SelectPropertyValue};
+use notion_client::objects::rich_text::{RichText, Text};
+use serde_json::Value;
+
+use crate::{Diagnostic, Outcome};
+
+/// The page-level data we found in a document's front matter.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FrontMatter {
+    pub properties: BTreeMap<String, PageProperty>,
+    pub icon: Option<Icon>,
+    pub cover: Option<File>,
+    /// `yaml` or `toml`, named the way mdast names the node.
+    pub kind: &'static str,
+    pub position: Option<Position>,
+    /// Fields we had to leave out because Notion wouldn't take them.
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl FrontMatter {
+    /// Look for a front matter node at the start of a parsed document. Returns `None`
+    /// if there isn't one, and a diagnostic if it doesn't parse or isn't a map of fields.
+    pub fn from_tree(tree: &Node) -> Result<Option<FrontMatter>, Diagnostic> {
+        let Some(node) = tree.children().and_then(|xs| xs.first()) else {
+            return Ok(None);
+        };
+        let (kind, parsed) = match node {
+            Node::Yaml(yaml) => (
+                "yaml",
+                serde_yaml::from_str::<Value>(yaml.value.as_str()).map_err(|e| e.to_string()),
+            ),
+            Node::Toml(toml) => (
+                "toml",
+                toml::from_str::<toml::Value>(toml.value.as_str())
+                    .map(toml_to_json)
+                    .map_err(|e| e.to_string()),
+            ),
+            _ => return Ok(None),
+        };
+        let fields = match parsed {
+            Ok(Value::Object(fields)) => fields,
+            Ok(_) => {
+                return Err(Diagnostic::for_node(
+                    node,
+                    Outcome::Unparsed("not a map of fields".to_string()),
+                ))
+            }
+            // TOML errors go on to draw the offending line; the first line says enough.
+            Err(e) => {
+                let reason = e.lines().next().unwrap_or_default().to_string();
+                return Err(Diagnostic::for_node(node, Outcome::Unparsed(reason)));
+            }
+        };
+        Ok(Some(FrontMatter {
+            kind,
+            position: node.position().cloned(),
+            ..FrontMatter::from_fields(fields, node)
+        }))
+    }
+
+    /// Each field becomes the most specific property type we can guess from its value.
+    /// `icon` and `cover` are special: they belong to the page, not its properties. Notion
+    /// turns down the whole page if either is something it can't use, so those are dropped.
+    fn from_fields(fields: serde_json::Map<String, Value>, node: &Node) -> FrontMatter {
+        let mut result = FrontMatter::default();
+        for (key, value) in fields {
+            match (key.as_str(), value) {
+                ("icon", Value::String(icon)) if is_url(icon.as_str()) => {
+                    result.icon = Some(Icon::File(external_file(icon)));
+                }
+                ("icon", Value::String(icon)) if is_single_emoji(icon.as_str()) => {
+                    result.icon = Some(Icon::Emoji(Emoji { emoji: icon }));
+                }
+                ("cover", Value::String(cover)) if is_url(cover.as_str()) => {
+                    result.cover = Some(external_file(cover));
+                }
+                ("icon" | "cover", _) => {
+                    result.diagnostics.push(Diagnostic::for_node(node, Outcome::Dropped));
+                }
+                ("title", value) => {
+                    let title = vec![plain_rich_text(scalar_to_string(&value))];
+                    result
+                        .properties
+                        .insert("title".to_string(), PageProperty::Title { id: None, title });
+                }
+                (_, value) => {
+                    if let Some(property) = property_from_value(value) {
+                        result.properties.insert(key, property);
+                    }
+                }
+            }
+        }
+        result
+    }
+}
+
+fn property_from_value(value: Value) -> Option<PageProperty> {
+    let property = match value {
+        Value::Bool(checkbox) => PageProperty::Checkbox { id: None, checkbox },
+        Value::Number(number) => PageProperty::Number {
+            id: None,
+            number: Some(number),
+        },
+        Value::String(input) => {
+            if let Some(date) = parse_date(input.as_str()) {
+                PageProperty::Date {
+                    id: None,
+                    date: Some(DatePropertyValue {
+                        start: Some(date),
+                        end: None,
+                        time_zone: None,
+                    }),
+                }
+            } else if is_url(input.as_str()) {
+                PageProperty::Url {
+                    id: None,
+                    url: Some(input),
+                }
+            } else {
+                PageProperty::RichText {
+                    id: None,
+                    rich_text: vec![plain_rich_text(input)],
+                }
+            }
+        }
+        Value::Array(items) => {
+            // Notion does not allow commas in select option names.
+            let multi_select = items
+                .iter()
+                .map(scalar_to_string)
+                .filter(|xs| !xs.is_empty())
+                .map(|name| SelectPropertyValue {
+                    id: None,
+                    name: Some(name.replace(',', " ")),
+                    color: None,
+                })
+                .collect();
+            PageProperty::MultiSelect { id: None, multi_select }
+        }
+        Value::Null | Value::Object(_) => return None,
+    };
+    Some(property)
+}
+
+fn parse_date(input: &str) -> Option<DateOrDateTime> {
+    if let Ok(date) = NaiveDate::parse_from_str(input, "%Y-%m-%d") {
+        return Some(DateOrDateTime::Date(date));
+    }
+    if let Ok(datetime) = DateTime::parse_from_rfc3339(input) {
+        return Some(DateOrDateTime::DateTime(datetime.with_timezone(&Utc)));
+    }
+    // Front matter dates without an offset are assumed to be UTC.
+    ["%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(input, format).ok())
+        .map(|naive| DateOrDateTime::DateTime(naive.and_utc()))
+}
+
+fn is_url(input: &str) -> bool {
+    input.starts_with("https://") || input.starts_with("http://")
+}
+
+/// Is this one emoji, the only kind of text Notion takes as an icon? That's a pictograph,
+/// perhaps with a variation selector, skin tone, or tags, and perhaps joined to more
+/// of the same; a flag made of two regional indicators; or a keycap.
+fn is_single_emoji(input: &str) -> bool {
+    let chars: Vec<char> = input.chars().collect();
+    let regional = |c: &char| ('\u{1F1E6}'..='\u{1F1FF}').contains(c);
+    if chars.len() == 2 && chars.iter().all(regional) {
+        return true;
+    }
+    let keycap = |c: &char| c.is_ascii_digit() || *c == '#' || *c == '*';
+    if let [base, rest @ ..] = chars.as_slice() {
+        if keycap(base) && (rest == ['\u{20E3}'] || rest == ['\u{FE0F}', '\u{20E3}']) {
+            return true;
+        }
+    }
+    let modifier =
+        |c: &char| matches!(c, '\u{FE0E}' | '\u{FE0F}' | '\u{1F3FB}'..='\u{1F3FF}' | '\u{E0020}'..='\u{E007F}');
+    let mut joined = true;
+    for c in chars.iter() {
+        if joined && is_pictograph(*c) {
+            joined = false;
+        } else if !joined && *c == '\u{200D}' {
+            joined = true;
+        } else if joined || !modifier(c) {
+            return false;
+        }
+    }
+    !chars.is_empty() && !joined
+}
+
+/// Characters that can stand alone as an emoji.
+fn is_pictograph(c: char) -> bool {
+    matches!(
+        c,
+        '\u{00A9}'
+            | '\u{00AE}'
+            | '\u{203C}'
+            | '\u{2049}'
+            | '\u{2122}'
+            | '\u{2139}'
+            | '\u{2194}'..='\u{21AA}'
+            | '\u{2300}'..='\u{23FF}'
+            | '\u{24C2}'
+            | '\u{25AA}'..='\u{25FE}'
+            | '\u{2600}'..='\u{27BF}'
+            | '\u{2934}'..='\u{2935}'
+            | '\u{2B00}'..='\u{2BFF}'
+            | '\u{3030}'
+            | '\u{303D}'
+            | '\u{3297}'
+            | '\u{3299}'
+            | '\u{1F000}'..='\u{1FAFF}'
+    )
+}
+
+fn scalar_to_string(value: &Value) -> String {
+    match value {
+        Value::String(xs) => xs.clone(),
+        Value::Null => String::new(),
+        other => other.to_string(),
+    }
+}
+
+fn external_file(url: String) -> File {
+    File::External {
+        external: ExternalFile { url },
+    }
+}
+
+fn plain_rich_text(input: String) -> RichText {
+    RichText::Text {
+        text: Text {
+            content: input.clone(),
+            link: None,
+        },
+        annotations: None,
+        plain_text: Some(input),
+        href: None,
+    }
+}
+
+/// TOML has a native datetime type that JSON lacks; it becomes a string here and
+/// gets recognized as a date later on.
+fn toml_to_json(value: toml::Value) -> Value {
+    match value {
+        toml::Value::String(xs) => Value::String(xs),
+        toml::Value::Integer(xs) => Value::from(xs),
+        toml::Value::Float(xs) => serde_json::Number::from_f64(xs)
+            .map(Value::Number)
+            .unwrap_or(Value::Null),
+        toml::Value::Boolean(xs) => Value::Bool(xs),
+        toml::Value::Datetime(xs) => Value::String(xs.to_string()),
+        toml::Value::Array(items) => Value::Array(items.into_iter().map(toml_to_json).collect()),
+        toml::Value::Table(table) => Value::Object(table.into_iter().map(|(k, v)| (k, toml_to_json(v))).collect()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use markdown::to_mdast;
+
+    use super::*;
+
+    #[test]
+    fn yaml() {
+        let input = include_str!("../fixtures/front_matter.md");
+        let tree =
+            to_mdast(input, &crate::parse_options(&Default::default())).expect("markdown parsing should succeed");
+        let found = FrontMatter::from_tree(&tree)
+            .expect("the fixture's front matter parses")
+            .expect("the fixture has front matter");
+
+        assert!(matches!(
+            found.properties.get("title"),
+            Some(PageProperty::Title { .. })
+        ));
+        match found.properties.get("tags") {
+            Some(PageProperty::MultiSelect { multi_select, .. }) => {
+                let names: Vec<&str> = multi_select.iter().filter_map(|xs| xs.name.as_deref()).collect();
+                assert_eq!(names, vec!["oncall", "databases", "postgres  mostly"]);
+            }
+            other => panic!("expected tags to be a multi-select; got {other:?}"),
+        }
+        assert!(matches!(
+            found.properties.get("reviewed"),
+            Some(PageProperty::Date { .. })
+        ));
+        assert!(matches!(found.properties.get("source"), Some(PageProperty::Url { .. })));
+        assert!(matches!(
+            found.properties.get("published"),
+            Some(PageProperty::Checkbox { checkbox: true, .. })
+        ));
+        assert!(matches!(
+            found.properties.get("priority"),
+            Some(PageProperty::Number { .. })
+        ));
+        assert!(!found.properties.contains_key("icon"));
+        assert!(matches!(found.icon, Some(Icon::Emoji(_))));
+        assert!(matches!(found.cover, Some(File::External { .. })));
+    }
+
+    #[test]
+    fn toml() {
+        let input = "+++\ntitle = \"From TOML\"\nupdated = 2024-03-18T10:00:00Z\n+++\n\nBody text.\n";
+        let tree =
+            to_mdast(input, &crate::parse_options(&Default::default())).expect("markdown parsing should succeed");
+        let found = FrontMatter::from_tree(&tree)
+            .expect("this front matter parses")
+            .expect("this input has front matter");
+        assert!(matches!(
+            found.properties.get("title"),
+            Some(PageProperty::Title { .. })
+        ));
+        match found.properties.get("updated") {
+            Some(PageProperty::Date { date: Some(date), .. }) => {
+                assert!(matches!(date.start, Some(DateOrDateTime::DateTime(_))));
+            }
+            other => panic!("expected a date; got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn unparseable() {
+        let options = crate::parse_options(&Default::default());
+        let yaml = to_mdast("---\ntags: [oncall\n---\n\nBody.\n", &options).expect("markdown parsing should succeed");
+        let diagnostic = FrontMatter::from_tree(&yaml).expect_err("the list is never closed");
+        assert_eq!(diagnostic.node, "yaml");
+        assert_eq!(diagnostic.position, Some((1, 1)));
+        assert!(matches!(diagnostic.outcome, Outcome::Unparsed(_)));
+
+        let list =
+            to_mdast("---\n- just\n- a list\n---\n\nBody.\n", &options).expect("markdown parsing should succeed");
+        let diagnostic = FrontMatter::from_tree(&list).expect_err("a list has no fields");
+        assert_eq!(diagnostic.to_string(), "1:1: yaml ignored: not a map of fields");
+
+        let none = to_mdast("Body.\n", &options).expect("markdown parsing should succeed");
+        assert_eq!(FrontMatter::from_tree(&none), Ok(None));
+    }
+
+    #[test]
+    fn icons_and_covers_notion_would_reject() {
+        assert!(is_single_emoji("🚒"));
+        assert!(is_single_emoji("❤️"));
+        assert!(is_single_emoji("👩🏽‍🚒"));
+        assert!(is_single_emoji("🇳🇿"));
+        assert!(is_single_emoji("#️⃣"));
+        assert!(!is_single_emoji(""));
+        assert!(!is_single_emoji("rocket"));
+        assert!(!is_single_emoji(":rocket:"));
+        assert!(!is_single_emoji("🚒🚒"));
+        assert!(!is_single_emoji("🚒 fire"));
+
+        let input = "---\ntitle: Odd\nicon: \":rocket:\"\ncover: images/cover.png\n---\n\nBody.\n";
+        let tree =
+            to_mdast(input, &crate::parse_options(&Default::default())).expect("markdown parsing should succeed");
+        let found = FrontMatter::from_tree(&tree)
+            .expect("this front matter parses")
+            .expect("this input has front matter");
+        assert_eq!(found.icon, None);
+        assert_eq!(found.cover, None);
+        assert_eq!(found.properties.len(), 1, "{:?}", found.properties);
+        assert_eq!(found.diagnostics.len(), 2);
+        assert!(found.diagnostics.iter().all(|xs| xs.outcome == Outcome::Dropped));
+    }
+
+    #[test]
+    fn front_matter_is_not_content() {
+        let input = include_str!("../fixtures/front_matter.md");
+        let blocks = crate::convert(input);
+        assert_eq!(blocks.len(), 2);
+        assert!(matches!(
+            blocks[0].block_type,
+            notion_client::objects::block::BlockType::Heading1 { .. }
+        ));
+    }
+}
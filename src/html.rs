@@ -0,0 +1,296 @@
+//! Just enough HTML to cope with what Nuclino exports and people write into GFM by hand.
+//! This is not a real HTML parser. It splits a fragment into tags and text, and leaves
+//! it to the converter to decide which tags it understands.
+
+/// A piece of an HTML fragment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Token {
+    Text(String),
+    /// An opening or self-closing tag. Names are lowercased.
+    Open {
+        name: String,
+        attrs: Vec<(String, String)>,
+    },
+    Close(String),
+    Comment,
+}
+
+impl Token {
+    pub(crate) fn attr(&self, wanted: &str) -> Option<&str> {
+        match self {
+            Token::Open { attrs, .. } => attrs
+                .iter()
+                .find(|(name, _)| name == wanted)
+                .map(|(_, value)| value.as_str()),
+            _ => None,
+        }
+    }
+}
+
+/// Split an HTML fragment into tokens. Anything that doesn't look like a tag is text.
+pub(crate) fn tokenize(input: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut rest = input;
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix("<!--") {
+            rest = after.find("-->").map_or("", |end| &after[end + 3..]);
+            tokens.push(Token::Comment);
+            continue;
+        }
+        let tag = rest
+            .strip_prefix('<')
+            .and_then(|after| after.find('>').map(|end| &after[..end]))
+            .and_then(|inner| parse_tag(inner).map(|token| (token, inner.len() + 2)));
+        if let Some((token, len)) = tag {
+            tokens.push(token);
+            rest = &rest[len..];
+            continue;
+        }
+        // Text runs up to the next thing that might be a tag.
+        let end = rest
+            .char_indices()
+            .skip(1)
+            .find(|(_, c)| *c == '<')
+            .map_or(rest.len(), |(idx, _)| idx);
+        let text = decode_entities(&rest[..end]);
+        match tokens.last_mut() {
+            Some(Token::Text(previous)) => previous.push_str(text.as_str()),
+            _ => tokens.push(Token::Text(text)),
+        }
+        rest = &rest[end..];
+    }
+    tokens
+}
+
+/// The inside of a tag, between the angle brackets.
+fn parse_tag(inner: &str) -> Option<Token> {
+    if let Some(name) = inner.strip_prefix('/') {
+        let name = name.trim();
+        if !is_tag_name(name) {
+            return None;
+        }
+        return Some(Token::Close(name.to_lowercase()));
+    }
+    let inner = inner.strip_suffix('/').unwrap_or(inner);
+    let name_end = inner.find(|c: char| c.is_whitespace()).unwrap_or(inner.len());
+    let name = &inner[..name_end];
+    if !is_tag_name(name) {
+        return None;
+    }
+    Some(Token::Open {
+        name: name.to_lowercase(),
+        attrs: parse_attrs(&inner[name_end..]),
+    })
+}
+
+fn is_tag_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic()) && name.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+/// `name="value"`, `name='value'`, `name=value`, or a bare `name`.
+fn parse_attrs(input: &str) -> Vec<(String, String)> {
+    let mut attrs = Vec::new();
+    let mut rest = input.trim_start();
+    while !rest.is_empty() {
+        let name_end = rest.find(|c: char| c.is_whitespace() || c == '=').unwrap_or(rest.len());
+        let name = rest[..name_end].to_lowercase();
+        rest = rest[name_end..].trim_start();
+        let value = if let Some(after) = rest.strip_prefix('=') {
+            let after = after.trim_start();
+            let (value, remaining) = match after.chars().next() {
+                Some(quote @ ('"' | '\'')) => {
+                    let body = &after[1..];
+                    let end = body.find(quote).unwrap_or(body.len());
+                    (&body[..end], body.get(end + 1..).unwrap_or(""))
+                }
+                _ => {
+                    let end = after.find(char::is_whitespace).unwrap_or(after.len());
+                    (&after[..end], &after[end..])
+                }
+            };
+            rest = remaining.trim_start();
+            decode_entities(value)
+        } else {
+            String::new()
+        };
+        if !name.is_empty() {
+            attrs.push((name, value));
+        }
+    }
+    attrs
+}
+
+/// The handful of entities that actually turn up, plus numeric ones.
+fn decode_entities(input: &str) -> String {
+    let mut output = String::with_capacity(input.len());
+    let mut rest = input;
+    while let Some(start) = rest.find('&') {
+        output.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let decoded = rest.find(';').filter(|end| *end <= 10).and_then(|end| {
+            let entity = &rest[1..end];
+            let c = match entity {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "nbsp" => Some('\u{a0}'),
+                _ => entity
+                    .strip_prefix("#x")
+                    .or_else(|| entity.strip_prefix("#X"))
+                    .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                    .or_else(|| entity.strip_prefix('#').and_then(|dec| dec.parse().ok()))
+                    .and_then(char::from_u32),
+            };
+            c.map(|c| (c, end + 1))
+        });
+        if let Some((c, len)) = decoded {
+            output.push(c);
+            rest = &rest[len..];
+        } else {
+            output.push('&');
+            rest = &rest[1..];
+        }
+    }
+    output.push_str(rest);
+    output
+}
+
+/// Notion has no superscript or subscript annotation, so we use the Unicode characters
+/// when every character in the text has one. Otherwise the text is left alone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Script {
+    Super,
+    Sub,
+}
+
+impl Script {
+    /// The text in this script, or a note on the characters that have no form in it.
+    pub(crate) fn apply(&self, input: &str) -> Result<String, String> {
+        let mut missing = String::new();
+        let scripted: String = input
+            .chars()
+            .filter_map(|c| {
+                let mapped = match self {
+                    Script::Super => superscript(c),
+                    Script::Sub => subscript(c),
+                };
+                if mapped.is_none() && !missing.contains(c) {
+                    missing.push(c);
+                }
+                mapped
+            })
+            .collect();
+        if missing.is_empty() {
+            return Ok(scripted);
+        }
+        let name = match self {
+            Script::Super => "superscript",
+            Script::Sub => "subscript",
+        };
+        Err(format!("no {name} form for {missing:?}, so {input:?} is plain text"))
+    }
+}
+
+fn superscript(c: char) -> Option<char> {
+    let mapped = match c {
+        '0' => '⁰',
+        '1' => '¹',
+        '2' => '²',
+        '3' => '³',
+        '4' => '⁴',
+        '5' => '⁵',
+        '6' => '⁶',
+        '7' => '⁷',
+        '8' => '⁸',
+        '9' => '⁹',
+        '+' => '⁺',
+        '-' => '⁻',
+        '=' => '⁼',
+        '(' => '⁽',
+        ')' => '⁾',
+        'i' => 'ⁱ',
+        'n' => 'ⁿ',
+        ' ' => ' ',
+        _ => return None,
+    };
+    Some(mapped)
+}
+
+fn subscript(c: char) -> Option<char> {
+    let mapped = match c {
+        '0' => '₀',
+        '1' => '₁',
+        '2' => '₂',
+        '3' => '₃',
+        '4' => '₄',
+        '5' => '₅',
+        '6' => '₆',
+        '7' => '₇',
+        '8' => '₈',
+        '9' => '₉',
+        '+' => '₊',
+        '-' => '₋',
+        '=' => '₌',
+        '(' => '₍',
+        ')' => '₎',
+        'a' => 'ₐ',
+        'e' => 'ₑ',
+        'o' => 'ₒ',
+        'x' => 'ₓ',
+        ' ' => ' ',
+        _ => return None,
+    };
+    Some(mapped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn open(name: &str, attrs: &[(&str, &str)]) -> Token {
+        Token::Open {
+            name: name.to_string(),
+            attrs: attrs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+        }
+    }
+
+    #[test]
+    fn tokens() {
+        assert_eq!(tokenize("<br>"), vec![open("br", &[])]);
+        assert_eq!(tokenize("<BR/>"), vec![open("br", &[])]);
+        assert_eq!(
+            tokenize("<a href=\"https://example.com/?a=1&amp;b=2\" target=_blank>x &lt; y</a>"),
+            vec![
+                open("a", &[("href", "https://example.com/?a=1&b=2"), ("target", "_blank")]),
+                Token::Text("x < y".to_string()),
+                Token::Close("a".to_string()),
+            ]
+        );
+        assert_eq!(
+            tokenize("<!-- hidden --><img src='x.png' alt=\"An image\" />"),
+            vec![Token::Comment, open("img", &[("src", "x.png"), ("alt", "An image")])]
+        );
+        // Not tags at all.
+        assert_eq!(tokenize("1 < 2 > 0"), vec![Token::Text("1 < 2 > 0".to_string())]);
+        assert_eq!(
+            tokenize("&#65;&#x42; &bogus;"),
+            vec![Token::Text("AB &bogus;".to_string())]
+        );
+        assert_eq!(
+            tokenize("ünïcödé<b>"),
+            vec![Token::Text("ünïcödé".to_string()), open("b", &[])]
+        );
+    }
+
+    #[test]
+    fn scripts() {
+        assert_eq!(Script::Super.apply("2").as_deref(), Ok("²"));
+        assert_eq!(Script::Sub.apply("2").as_deref(), Ok("₂"));
+        assert_eq!(
+            Script::Super.apply("nth"),
+            Err("no superscript form for \"th\", so \"nth\" is plain text".to_string())
+        );
+    }
+}
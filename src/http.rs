@@ -0,0 +1,158 @@
+//! A Notion client that reports everything the retry layer needs to know about a
+//! failure: the status, the error code, and any `Retry-After` header. It sends and
+//! receives the same request and response types as the notion-client crate.
+
+use std::time::Duration;
+
+use notion_client::endpoints::blocks::append::request::AppendBlockChildrenRequest;
+use notion_client::endpoints::blocks::append::response::AppendBlockChildrenResponse;
+use notion_client::endpoints::blocks::update::request::UpdateABlockRequest;
+use notion_client::endpoints::pages::create::request::CreateAPageRequest;
+use notion_client::objects::block::Block;
+use notion_client::objects::error::Error as ErrorBody;
+use notion_client::objects::page::Page as NotionPage;
+use reqwest::header::{self, HeaderMap, HeaderValue};
+use reqwest::{RequestBuilder, Response};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::{ApiError, NotionApi, RateLimiter, RetryPolicy};
+
+const NOTION_URI: &str = "https://api.notion.com/v1";
+const NOTION_VERSION: &str = "2022-06-28";
+
+#[derive(Debug, Clone)]
+pub struct NotionHttp {
+    client: reqwest::Client,
+    base: String,
+    retries: RetryPolicy,
+    limiter: RateLimiter,
+}
+
+impl NotionHttp {
+    pub fn new(token: &str) -> Result<Self, ApiError> {
+        let mut headers = HeaderMap::new();
+        headers.insert("Notion-Version", HeaderValue::from_static(NOTION_VERSION));
+        headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        let mut auth = HeaderValue::from_str(format!("Bearer {token}").as_str())
+            .map_err(|_| ApiError::Invalid("the API key can't go in a header".to_string()))?;
+        auth.set_sensitive(true);
+        headers.insert(header::AUTHORIZATION, auth);
+
+        let client = reqwest::Client::builder()
+            .default_headers(headers)
+            .build()
+            .map_err(|e| ApiError::Invalid(e.to_string()))?;
+        Ok(Self {
+            client,
+            base: NOTION_URI.to_string(),
+            retries: RetryPolicy::default(),
+            limiter: RateLimiter::default(),
+        })
+    }
+
+    /// Send requests somewhere other than the real Notion API, like a local stand-in.
+    pub fn with_base_url(mut self, base: &str) -> Self {
+        self.base = base.trim_end_matches('/').to_string();
+        self
+    }
+
+    pub fn with_retries(mut self, retries: RetryPolicy) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Clones made after this share its rate limiter, however many tasks they're in.
+    pub fn with_rate_limit(mut self, per_second: f64) -> Self {
+        self.limiter = RateLimiter::new(per_second);
+        self
+    }
+
+    async fn send<T: DeserializeOwned>(&self, request: RequestBuilder, body: &impl Serialize) -> Result<T, ApiError> {
+        let json = serde_json::to_string(body).map_err(|e| ApiError::Invalid(e.to_string()))?;
+        let response = request
+            .body(json)
+            .send()
+            .await
+            .map_err(|e| ApiError::Transport(e.to_string()))?;
+        read_response(response).await
+    }
+}
+
+async fn read_response<T: DeserializeOwned>(response: Response) -> Result<T, ApiError> {
+    let status = response.status();
+    let retry_after = response
+        .headers()
+        .get(header::RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(parse_retry_after);
+    let body = response.text().await.map_err(|e| ApiError::Transport(e.to_string()))?;
+
+    if status.is_success() {
+        return serde_json::from_str(body.as_str()).map_err(|e| ApiError::Invalid(format!("{e}: {body}")));
+    }
+    // Gateways in front of Notion answer with HTML, so there might not be an error object.
+    let (code, message) = match serde_json::from_str::<ErrorBody>(body.as_str()) {
+        Ok(error) => (error.code, error.message),
+        Err(_) => (
+            "http_error".to_string(),
+            status.canonical_reason().unwrap_or("no reason given").to_string(),
+        ),
+    };
+    Err(ApiError::Status {
+        status: status.as_u16(),
+        code,
+        message,
+        retry_after,
+    })
+}
+
+/// Notion sends a number of seconds. HTTP allows a date here too, but we'd rather back
+/// off on our own schedule than parse one.
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    let seconds: f64 = value.trim().parse().ok()?;
+    (seconds.is_finite() && seconds >= 0.0).then(|| Duration::from_secs_f64(seconds))
+}
+
+impl NotionApi for NotionHttp {
+    async fn create_page(&self, request: CreateAPageRequest) -> Result<NotionPage, ApiError> {
+        let url = format!("{}/pages", self.base);
+        self.send(self.client.post(url), &request).await
+    }
+
+    async fn append_children(
+        &self,
+        parent_id: &str,
+        request: AppendBlockChildrenRequest,
+    ) -> Result<Vec<Block>, ApiError> {
+        let url = format!("{}/blocks/{parent_id}/children", self.base);
+        let response: AppendBlockChildrenResponse = self.send(self.client.patch(url), &request).await?;
+        Ok(response.results)
+    }
+
+    async fn update_block(&self, block_id: &str, request: UpdateABlockRequest) -> Result<Option<Block>, ApiError> {
+        let url = format!("{}/blocks/{block_id}", self.base);
+        // The response is a block, which is what this type holds when it's flattened.
+        let response: UpdateABlockRequest = self.send(self.client.patch(url), &request).await?;
+        Ok(response.block)
+    }
+
+    async fn retrieve_page(&self, page_id: &str) -> Result<NotionPage, ApiError> {
+        let url = format!("{}/pages/{page_id}", self.base);
+        let response = self
+            .client
+            .get(url)
+            .send()
+            .await
+            .map_err(|e| ApiError::Transport(e.to_string()))?;
+        read_response(response).await
+    }
+
+    fn retry_policy(&self) -> RetryPolicy {
+        self.retries
+    }
+
+    fn rate_limiter(&self) -> Option<&RateLimiter> {
+        Some(&self.limiter)
+    }
+}
@@ -0,0 +1,183 @@
+//! Mapping fenced code block info strings to the languages Notion knows about.
+
+use notion_client::objects::block::Language;
+
+/// Find the Notion language for the language name in a fenced code block's info string.
+/// Matching is case-insensitive and understands the common aliases people use in
+/// Markdown. Anything we don't recognize is plain text.
+pub fn language_for(name: &str) -> Language {
+    let lowered = name.trim().to_lowercase();
+    match lowered.as_str() {
+        "abap" => Language::Abap,
+        "arduino" | "ino" => Language::Arduino,
+        "bash" | "zsh" => Language::Bash,
+        "basic" => Language::Basic,
+        "c" | "h" => Language::C,
+        "clojure" | "clj" | "cljs" | "edn" => Language::Clojure,
+        "coffeescript" | "coffee" => Language::Coffeescript,
+        "c++" | "cpp" | "cxx" | "cc" | "hpp" => Language::CPlusPlus,
+        "c#" | "csharp" | "cs" => Language::CSharp,
+        "css" => Language::Css,
+        "dart" => Language::Dart,
+        "diff" | "patch" => Language::Diff,
+        "docker" | "dockerfile" | "containerfile" => Language::Docker,
+        "elixir" | "ex" | "exs" => Language::Elixir,
+        "elm" => Language::Elm,
+        "erlang" | "erl" => Language::Erlang,
+        "flow" => Language::Flow,
+        "fortran" | "f90" | "f95" => Language::Fortran,
+        "f#" | "fsharp" | "fs" => Language::FSharp,
+        "gherkin" | "cucumber" | "feature" => Language::Gherkin,
+        "glsl" => Language::Glsl,
+        "go" | "golang" => Language::Go,
+        "graphql" | "gql" => Language::Graphql,
+        "groovy" | "gradle" => Language::Groovy,
+        "haskell" | "hs" => Language::Haskell,
+        "html" | "htm" | "xhtml" => Language::Html,
+        "java" => Language::Java,
+        "javascript" | "js" | "jsx" | "mjs" | "cjs" | "node" => Language::Javascript,
+        "json" | "jsonc" | "json5" => Language::Json,
+        "julia" | "jl" => Language::Julia,
+        "kotlin" | "kt" | "kts" => Language::Kotlin,
+        "latex" | "tex" => Language::Latex,
+        "less" => Language::Less,
+        "lisp" | "elisp" | "emacs-lisp" | "common-lisp" => Language::Lisp,
+        "livescript" | "ls" => Language::Livescript,
+        "lua" => Language::Lua,
+        "makefile" | "make" | "mk" => Language::Makefile,
+        "markdown" | "md" | "mdx" => Language::Markdown,
+        "markup" => Language::Markup,
+        "matlab" => Language::Matlab,
+        "mermaid" => Language::Mermaid,
+        "nix" => Language::Nix,
+        "objective-c" | "objectivec" | "objc" | "obj-c" => Language::ObjectiveC,
+        "ocaml" | "ml" => Language::Ocaml,
+        "pascal" | "delphi" => Language::Pascal,
+        "perl" | "pl" | "pm" => Language::Perl,
+        "php" => Language::Php,
+        "powershell" | "ps" | "ps1" | "pwsh" => Language::Powershell,
+        "prolog" => Language::Prolog,
+        "protobuf" | "proto" => Language::Protobuf,
+        "python" | "py" | "py3" | "python3" => Language::Python,
+        "r" => Language::R,
+        "reason" | "re" => Language::Reason,
+        "ruby" | "rb" => Language::Ruby,
+        "rust" | "rs" => Language::Rust,
+        "sass" => Language::Sass,
+        "scala" | "sc" => Language::Scala,
+        "scheme" | "scm" | "racket" => Language::Scheme,
+        "scss" => Language::Scss,
+        "shell" | "sh" | "console" | "shell-session" | "fish" => Language::Shell,
+        "sql" | "psql" | "postgresql" | "mysql" | "sqlite" => Language::Sql,
+        "swift" => Language::Swift,
+        "solidity" | "sol" => Language::Solidity,
+        "typescript" | "ts" | "tsx" | "mts" | "cts" => Language::Typescript,
+        "vb.net" | "vbnet" => Language::VbNet,
+        "verilog" | "v" => Language::Verilog,
+        "vhdl" => Language::Vhdl,
+        "visual basic" | "vb" | "vba" | "vbscript" => Language::VisualBasic,
+        "webassembly" | "wasm" | "wat" => Language::Webassembly,
+        "xml" | "svg" | "xsl" | "plist" => Language::Xml,
+        "yaml" | "yml" => Language::Yaml,
+        _ => Language::PlainText,
+    }
+}
+
+/// The language name to put on a fenced code block, or nothing for plain text. This is
+/// Notion's own name for the language when [`language_for`] understands it.
+pub fn info_string_for(language: &Language) -> Option<String> {
+    match language {
+        Language::PlainText => return None,
+        Language::VisualBasic => return Some("vb".to_string()),
+        _ => {}
+    }
+    let name = serde_json::to_value(language).ok()?.as_str()?.to_owned();
+    [name.clone(), name.replace(' ', "")]
+        .into_iter()
+        .find(|xs| !xs.contains(char::is_whitespace) && language_for(xs) == *language)
+}
+
+/// Turn whatever follows the language in a fenced code block's info string into
+/// caption text. A `title="…"` attribute wins if there is one; otherwise we use
+/// the metadata as-is.
+pub fn caption_from_meta(meta: &str) -> Option<String> {
+    let meta = meta.trim();
+    if meta.is_empty() {
+        return None;
+    }
+    let title = meta_attributes(meta)
+        .into_iter()
+        .find_map(|(key, value)| (key == "title" && !value.is_empty()).then_some(value));
+    Some(title.unwrap_or(meta).to_owned())
+}
+
+/// Split metadata like `title="a b" linenos` into key-value pairs, unquoting the values.
+/// Anything without an `=` is a key with an empty value.
+fn meta_attributes(meta: &str) -> Vec<(&str, &str)> {
+    let mut attributes = Vec::new();
+    let mut rest = meta.trim_start();
+    while !rest.is_empty() {
+        let key_end = rest.find(|c: char| c == '=' || c.is_whitespace()).unwrap_or(rest.len());
+        let (key, after) = rest.split_at(key_end);
+        let Some(after) = after.strip_prefix('=') else {
+            attributes.push((key, ""));
+            rest = after.trim_start();
+            continue;
+        };
+        let (value, after) = match after.chars().next() {
+            Some(quote @ ('"' | '\'')) => {
+                let body = &after[1..];
+                let end = body.find(quote).unwrap_or(body.len());
+                (&body[..end], body.get(end + 1..).unwrap_or_default())
+            }
+            _ => {
+                let end = after.find(char::is_whitespace).unwrap_or(after.len());
+                after.split_at(end)
+            }
+        };
+        attributes.push((key, value));
+        rest = after.trim_start();
+    }
+    attributes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aliases() {
+        assert_eq!(language_for("rs"), Language::Rust);
+        assert_eq!(language_for("Rust"), Language::Rust);
+        assert_eq!(language_for("sh"), Language::Shell);
+        assert_eq!(language_for("bash"), Language::Bash);
+        assert_eq!(language_for("yml"), Language::Yaml);
+        assert_eq!(language_for("c++"), Language::CPlusPlus);
+        assert_eq!(language_for("Dockerfile"), Language::Docker);
+        assert_eq!(language_for("no-such-language"), Language::PlainText);
+    }
+
+    #[test]
+    fn info_strings() {
+        assert_eq!(info_string_for(&Language::Rust).as_deref(), Some("rust"));
+        assert_eq!(info_string_for(&Language::CPlusPlus).as_deref(), Some("c++"));
+        assert_eq!(info_string_for(&Language::VisualBasic).as_deref(), Some("vb"));
+        assert_eq!(info_string_for(&Language::PlainText), None);
+    }
+
+    #[test]
+    fn captions() {
+        assert_eq!(caption_from_meta(""), None);
+        assert_eq!(
+            caption_from_meta(r#"title="src/main.rs""#).as_deref(),
+            Some("src/main.rs")
+        );
+        assert_eq!(caption_from_meta("title=x linenos").as_deref(), Some("x"));
+        assert_eq!(caption_from_meta("{1,3-4}").as_deref(), Some("{1,3-4}"));
+        assert_eq!(caption_from_meta(r#"subtitle="x""#).as_deref(), Some(r#"subtitle="x""#));
+        assert_eq!(
+            caption_from_meta(r#"pagetitle=x title='a b' linenos"#).as_deref(),
+            Some("a b")
+        );
+    }
+}
@@ -1,168 +1,496 @@
 //! This library exports two reusable functions, one that converts Markdown strings
 //! to Notion page content constructs and one that creates Notion pages.
 
+mod api;
+mod export;
+#[cfg(any(test, feature = "fake"))]
+mod fake;
+mod frontmatter;
+mod html;
+mod http;
+mod languages;
+mod limiter;
+mod options;
+mod report;
 mod retries;
 #[cfg(test)]
 mod tests;
-
-use std::collections::{BTreeMap, HashMap, VecDeque};
-
+mod urls;
+
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+
+use api::Offline;
+pub use api::{ApiError, NotionApi};
+pub use export::blocks_to_markdown;
+#[cfg(any(test, feature = "fake"))]
+pub use fake::FakeNotion;
+use frontmatter::FrontMatter;
+use html::{Script, Token};
+pub use http::NotionHttp;
+pub use limiter::RateLimiter;
 use markdown::mdast::{self, Node};
+use markdown::unist::Position;
 use markdown::{to_mdast, ParseOptions};
 use miette::{miette, Result};
 use notion_client::endpoints::pages::create::request::CreateAPageRequest;
-use notion_client::endpoints::Client;
 use notion_client::objects::block::*;
 use notion_client::objects::emoji::Emoji;
 use notion_client::objects::file::{ExternalFile, File};
 use notion_client::objects::page::{Page as NotionPage, PageProperty};
 use notion_client::objects::parent::Parent;
 use notion_client::objects::rich_text::{Annotations, Equation, Link, RichText, Text};
-pub use retries::{do_append, do_create};
+pub use options::{
+    ConvertOptions, FootnoteStyle, HeadingPolicy, HtmlPolicy, LinkRewriter, ParentKind, StandaloneLinks,
+};
+pub use report::{Diagnostic, Outcome};
+pub use retries::{do_append, do_create, do_update, RetryPolicy};
+use serde::Serialize;
+use urls::{Checked, Preview};
+
+/// The deepest level of nesting we'll allow in an API request: the blocks being
+/// appended can have children, and those children can have children.
+static MAX_NESTING: u8 = 2;
+
+/// The most blocks the API accepts in a single array of children.
+static MAX_CHILDREN: usize = 100;
 
-/// The deepest level of nesting we'll allow in an API request.
-static MAX_NESTING: u8 = 1;
+/// The most blocks the API accepts in a single request, counting nested children.
+static MAX_BLOCKS_PER_REQUEST: usize = 1000;
+
+/// The biggest request body the API accepts, less some room for the JSON around the
+/// blocks themselves.
+static MAX_PAYLOAD_BYTES: usize = 500_000 - 1000;
+
+/// The most text runs the API accepts in a single piece of rich text.
+static MAX_RICH_TEXT_RUNS: usize = 100;
+
+/// Prefix for the placeholder ids we give blocks during conversion. These never
+/// reach Notion; the page maker swaps them out as it uploads.
+static LOCAL_ID_PREFIX: &str = "nuc2not-local-";
 
 /// Convert a string slice containing Markdown into a Notion Page in your Notion team.
 /// This function makes as many API calls as necessary to create the page with
 /// all content, working around limits on body size and nesting depth.
+///
+/// If the Markdown starts with YAML or TOML front matter, its `title` field becomes the
+/// page title, unless you pass in a title of your own. `icon` and `cover` fields set the
+/// page icon and cover. Notion only accepts other properties on pages whose parent is a
+/// database, so the page goes up without the remaining fields, and
+/// [`create_page_with_report`] lists them. To create the page in a database instead,
+/// with fields like tags and dates as properties, use [`create_page_with`] and
+/// [`ParentKind::Database`].
 pub async fn create_page(
-    client: &Client,
+    client: &impl NotionApi,
+    input: &str,
+    parent: &str,
+    properties: BTreeMap<String, PageProperty>,
+) -> Result<NotionPage> {
+    create_page_with(client, input, parent, properties, &ConvertOptions::default()).await
+}
+
+/// Just like `create_page`, with your choice of conversion options.
+pub async fn create_page_with(
+    client: &impl NotionApi,
     input: &str,
     parent: &str,
     properties: BTreeMap<String, PageProperty>,
+    options: &ConvertOptions,
 ) -> Result<NotionPage> {
-    let maker = PageMaker::new(client, parent, properties);
-    maker.make_page(input).await
+    create_page_with_report(client, input, parent, properties, options)
+        .await
+        .0
+}
+
+/// Just like `create_page_with`, and also reports what didn't make it to Notion intact.
+/// The report comes back even if the upload fails.
+pub async fn create_page_with_report(
+    client: &impl NotionApi,
+    input: &str,
+    parent: &str,
+    properties: BTreeMap<String, PageProperty>,
+    options: &ConvertOptions,
+) -> (Result<NotionPage>, Vec<Diagnostic>) {
+    let mut maker = PageMaker::new(client, parent, properties);
+    maker.options = options.clone();
+    let page = maker.make_page(input).await;
+    (page, maker.diagnostics)
+}
+
+/// Work out the requests `create_page_with` would make for this Markdown, without making
+/// any of them. Nothing here talks to Notion, so no API key is needed.
+pub async fn plan_page(input: &str, options: &ConvertOptions) -> Result<RequestPlan> {
+    let mut maker = PageMaker::new(&Offline, "planned-parent", BTreeMap::new());
+    maker.options = options.clone();
+    maker.plan = Some(RequestPlan::default());
+    let (_request, blocks) = maker.prepare_page(input)?;
+    maker.record(PlannedRequest::CreatePage);
+    maker.fill_page("planned-page", blocks).await?;
+    Ok(maker.plan.unwrap_or_default())
+}
+
+/// The requests `create_page` makes for a page, in order.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct RequestPlan {
+    pub requests: Vec<PlannedRequest>,
+    /// How many times a block had to be sent without its children, which then
+    /// went up in requests of their own.
+    pub splits: usize,
+}
+
+/// A single Notion API call.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "request", rename_all = "snake_case")]
+pub enum PlannedRequest {
+    /// Create the page itself.
+    CreatePage,
+    /// Append children to a block or page. `blocks` counts the top-level blocks in the
+    /// request and `total` counts them along with all their nested children.
+    Append {
+        parent: String,
+        blocks: usize,
+        total: usize,
+    },
+    /// Update a block's text so its in-document links point somewhere real.
+    Update { block: String },
+}
+
+impl RequestPlan {
+    pub fn count(&self, matching: impl Fn(&PlannedRequest) -> bool) -> usize {
+        self.requests.iter().filter(|xs| matching(xs)).count()
+    }
+}
+
+impl std::fmt::Display for RequestPlan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for request in self.requests.iter() {
+            match request {
+                PlannedRequest::CreatePage => writeln!(f, "create page")?,
+                PlannedRequest::Append { parent, blocks, total } => {
+                    writeln!(f, "append {blocks} blocks ({total} in all) to {parent}")?
+                }
+                PlannedRequest::Update { block } => writeln!(f, "update {block}")?,
+            }
+        }
+        let appends = self.count(|xs| matches!(xs, PlannedRequest::Append { .. }));
+        let updates = self.count(|xs| matches!(xs, PlannedRequest::Update { .. }));
+        write!(
+            f,
+            "{} requests: 1 create, {appends} appends, {updates} updates; {} nesting splits",
+            self.requests.len(),
+            self.splits
+        )
+    }
+}
+
+/// One level of blocks under a parent, packed into requests.
+#[derive(Debug, Default)]
+struct Level {
+    /// The blocks for each append request, in order.
+    batches: Vec<Vec<Block>>,
+    /// Children that have to wait for their parent to exist, keyed by the parent's
+    /// position in the level.
+    descend: Vec<(usize, VecDeque<Block>)>,
 }
 
 /// This name amused me, and I wanted to avoid passing a million arguments
 /// to some functions.
-struct PageMaker {
-    notion: Client,
+struct PageMaker<'a, A: NotionApi> {
+    notion: &'a A,
     parent: String,
     properties: BTreeMap<String, PageProperty>,
+    /// Children we can only append once their parent block exists, keyed by local id.
+    deferred: HashMap<String, Vec<Block>>,
+    /// In-document link targets, mapping fragments to local ids.
+    anchors: HashMap<String, String>,
+    /// Blocks with in-document links to fix up once everything exists, keyed by local id.
+    pending: HashMap<String, Block>,
+    /// The real ids of the blocks we've created, keyed by local id.
+    created: HashMap<String, String>,
+    /// When set, we're only planning: requests are recorded here instead of being sent.
+    plan: Option<RequestPlan>,
+    options: ConvertOptions,
+    /// Everything that didn't convert or upload cleanly.
+    diagnostics: Vec<Diagnostic>,
 }
 
-impl PageMaker {
-    pub fn new(client: &Client, parent_id: &str, properties: BTreeMap<String, PageProperty>) -> Self {
+impl<'a, A: NotionApi> PageMaker<'a, A> {
+    pub fn new(client: &'a A, parent_id: &str, properties: BTreeMap<String, PageProperty>) -> Self {
         PageMaker {
-            notion: client.clone(),
+            notion: client,
             parent: parent_id.to_owned(),
             properties,
+            deferred: HashMap::new(),
+            anchors: HashMap::new(),
+            pending: HashMap::new(),
+            created: HashMap::new(),
+            plan: None,
+            options: ConvertOptions::default(),
+            diagnostics: Vec::new(),
         }
     }
 
-    pub async fn make_page(&self, input: &str) -> Result<NotionPage> {
-        let blocks = convert(input);
+    /// Note a request in the plan. Returns false if we aren't planning, so it should be sent.
+    fn record(&mut self, request: PlannedRequest) -> bool {
+        let Some(plan) = self.plan.as_mut() else {
+            return false;
+        };
+        plan.requests.push(request);
+        true
+    }
+
+    pub async fn make_page(&mut self, input: &str) -> Result<NotionPage> {
+        let (new_page_req, blocks) = self.prepare_page(input)?;
+        let notion_page = do_create(self.notion, &new_page_req).await?;
+        self.fill_page(notion_page.id.as_str(), blocks).await?;
+        Ok(notion_page)
+    }
+
+    /// Convert the input and build the request that creates the page. The blocks come back
+    /// separately, because they go up in as many appends as they need.
+    fn prepare_page(&mut self, input: &str) -> Result<(CreateAPageRequest, Vec<Block>)> {
+        let Conversion {
+            blocks,
+            deferred,
+            anchors,
+            pending,
+            diagnostics,
+            front_matter,
+        } = convert_for_upload(input, &self.options);
+        self.deferred = deferred;
+        self.anchors = anchors;
+        self.pending = pending;
+        self.diagnostics = diagnostics;
         if blocks.is_empty() {
             // early return for readability
             return Err(miette!("Markdown AST has no children; is the markdown file empty?"));
         }
 
-        let parent = Parent::PageId {
-            page_id: self.parent.clone(),
+        let front_matter = front_matter.unwrap_or_default();
+        let (mut properties, parent) = match self.options.parent {
+            ParentKind::Database => (
+                front_matter.properties,
+                Parent::DatabaseId {
+                    database_id: self.parent.clone(),
+                },
+            ),
+            ParentKind::Page => {
+                // Pages under a page can't have any property but a title. Front matter
+                // fields that would be other properties have to go.
+                let (title, dropped): (BTreeMap<_, _>, BTreeMap<_, _>) =
+                    front_matter.properties.into_iter().partition(|(key, _)| key == "title");
+                self.diagnostics.extend(dropped.into_keys().map(|key| {
+                    let detail = format!("`{key}` field dropped; only pages in a database can have it as a property");
+                    Diagnostic::new(
+                        front_matter.kind,
+                        front_matter.position.as_ref(),
+                        Outcome::Truncated(detail),
+                    )
+                }));
+                let parent = Parent::PageId {
+                    page_id: self.parent.clone(),
+                };
+                (title, parent)
+            }
         };
+        properties.extend(self.properties.clone());
+
         let new_page_req = CreateAPageRequest {
-            parent: parent.clone(),
-            icon: None,
-            cover: None,
-            properties: self.properties.clone(),
+            parent,
+            icon: front_matter.icon,
+            cover: front_matter.cover,
+            properties,
             children: None,
         };
+        Ok((new_page_req, blocks))
+    }
 
-        let notion_page = do_create(&self.notion, &new_page_req, 0).await?;
-
-        // Now we have our first ID to hang children on!
-        let mut remaining = VecDeque::from(blocks);
-        self.append_children(notion_page.id.clone().as_str(), None, &mut remaining)
-            .await?;
+    /// Now we have our first ID to hang children on!
+    async fn fill_page(&mut self, page_id: &str, blocks: Vec<Block>) -> Result<()> {
+        self.append_children(page_id, VecDeque::from(blocks)).await?;
+        self.link_internal_refs(page_id).await
+    }
 
-        Ok(notion_page)
+    /// Footnote references and fragment links can only point at their targets once both
+    /// exist and have ids. Now that everything has been created, patch the referring blocks
+    /// with real links.
+    async fn link_internal_refs(&mut self, page_id: &str) -> Result<()> {
+        let pending = std::mem::take(&mut self.pending);
+        for (local_id, mut block) in pending {
+            let Some(block_id) = self.created.get(&local_id).cloned() else {
+                continue;
+            };
+            self.resolve_internal_links(page_id, &mut block);
+            block.id = None;
+            if !self.record(PlannedRequest::Update {
+                block: block_id.clone(),
+            }) {
+                do_update(self.notion, block_id.as_str(), &block).await?;
+            }
+        }
+        Ok(())
     }
 
-    async fn append_children(
-        &self,
-        parent_id: &str,
-        after_id: Option<String>,
-        to_be_appended: &mut VecDeque<Block>,
-    ) -> Result<()> {
-        // eprintln!(
-        //     "entering append_children({parent_id}, {after_id:?}, len={})",
-        //     to_be_appended.len()
-        // );
-        let mut after: Option<String> = after_id.clone();
-        let mut current_tranche: Vec<Block> = Vec::new(); // building the next list
-        while !to_be_appended.is_empty() {
-            if let Some(head) = to_be_appended.pop_front() {
-                // While the head of `remaining` has no children, push it onto the end of `blocks`
-                // for blocks with children, stop and look to see if the children violate depth limits.
-                if PageMaker::block_has_deep_children(0, &head) {
-                    // if so, hold that block and call append children on it one level at a time until we hit bottom.
-                    // This is not maximally efficient, BUT.
-                    let (copy, maybe_children) = split_block_from_children(head);
-                    current_tranche.push(copy);
-                    let created =
-                        do_append(&self.notion, parent_id, current_tranche.as_slice(), after.clone(), 0).await?;
-                    // snag the id from the last block in the request, which will be head's id
-                    let head_id = if let Some(last) = created.last() {
-                        if let Some(ref id) = last.id {
-                            id.clone()
-                        } else {
-                            parent_id.to_owned()
-                        }
-                    } else {
-                        // really quite impossible, which means my structure is wrong here
-                        parent_id.to_owned()
-                    };
-                    if let Some(mut head_children) = maybe_children {
-                        Box::pin(self.append_children(head_id.as_str(), None, &mut head_children)).await?;
-                    }
-                    current_tranche = Vec::new();
-                    // keep going with the rest of the list, now with the after-id of where we stopped
-                    Box::pin(self.append_children(parent_id, Some(head_id.clone()), to_be_appended)).await?;
+    /// Point every resolvable in-document link in this block's text at its target block.
+    /// Links whose target never got an id are left as plain text, and reported.
+    fn resolve_internal_links(&mut self, page_id: &str, block: &mut Block) {
+        let mut unresolved = Vec::new();
+        block_rich_text_mut(block).into_iter().for_each(|run| {
+            let Some(fragment) = internal_fragment(run).map(|xs| xs.to_string()) else {
+                return;
+            };
+            let target = self.anchors.get(&fragment).and_then(|local| self.created.get(local));
+            if let RichText::Text { text, href, .. } = run {
+                if let Some(target) = target {
+                    let url = block_url(page_id, target);
+                    text.link = Some(Link { url: url.clone() });
+                    *href = Some(url);
                 } else {
-                    current_tranche.push(head);
-                }
-                // Magic constant is an API limit. Make the request, then keep on going.
-                if current_tranche.len() == 100 {
-                    let created =
-                        do_append(&self.notion, parent_id, current_tranche.as_slice(), after.clone(), 0).await?;
-                    if let Some(last) = created.last() {
-                        after.clone_from(&last.id);
-                    }
-                    current_tranche = Vec::new();
+                    unresolved.push(Diagnostic::new(
+                        "link",
+                        None,
+                        Outcome::Unresolved(format!("block for #{fragment}")),
+                    ));
+                    *href = None;
                 }
             }
-        }
+        });
+        self.diagnostics.extend(unresolved);
+    }
 
-        if !current_tranche.is_empty() {
-            let _created = do_append(&self.notion, parent_id, current_tranche.as_slice(), after.clone(), 0).await?;
-        }
+    /// Send a batch of blocks. Local ids are stripped on the way out, and the real
+    /// ids that come back are noted so we can find those blocks again.
+    async fn send(&mut self, parent_id: &str, tranche: &[Block]) -> Result<Vec<Block>> {
+        let mut outgoing = tranche.to_vec();
+        let local_ids: Vec<Option<String>> = outgoing.iter_mut().map(|xs| xs.id.take()).collect();
+        let created = if let Some(plan) = self.plan.as_mut() {
+            let made = plan.requests.len();
+            plan.requests.push(PlannedRequest::Append {
+                parent: parent_id.to_owned(),
+                blocks: outgoing.len(),
+                total: outgoing.iter().map(count_blocks).sum(),
+            });
+            // Stand-in ids, so later requests have something to refer to.
+            outgoing
+                .iter()
+                .enumerate()
+                .map(|(idx, block)| Block {
+                    id: Some(format!("planned-{made}-{idx}")),
+                    ..block.clone()
+                })
+                .collect()
+        } else {
+            do_append(self.notion, parent_id, outgoing.as_slice(), None).await?
+        };
+        local_ids.into_iter().zip(created.iter()).for_each(|(local, made)| {
+            if let (Some(local), Some(real)) = (local, made.id.as_ref()) {
+                self.created.insert(local, real.clone());
+            }
+        });
+        Ok(created)
+    }
 
+    /// Append blocks to a parent, along with everything under them. Each block goes up
+    /// with as much of its subtree as a request can carry. A block whose children can't
+    /// come along goes up without them, next to its siblings, and its children follow
+    /// in requests of their own once Notion has given it an id.
+    async fn append_children(&mut self, parent_id: &str, blocks: VecDeque<Block>) -> Result<()> {
+        let Level { batches, descend } = self.plan_level(blocks);
+        let mut ids: Vec<Option<String>> = Vec::new();
+        for batch in batches {
+            let created = self.send(parent_id, batch.as_slice()).await?;
+            ids.extend(created.into_iter().map(|xs| xs.id));
+        }
+        for (idx, children) in descend {
+            let Some(Some(id)) = ids.get(idx).cloned() else {
+                return Err(miette!(
+                    "Notion didn't tell us the id of a block we still need to add children to"
+                ));
+            };
+            Box::pin(self.append_children(id.as_str(), children)).await?;
+        }
         Ok(())
     }
 
-    fn block_has_deep_children(nesting: u8, block: &Block) -> bool {
-        let maybe_kids = match block.block_type {
-            BlockType::BulletedListItem { ref bulleted_list_item } => &bulleted_list_item.children,
-            BlockType::NumberedListItem { ref numbered_list_item } => &numbered_list_item.children,
-            BlockType::Paragraph { ref paragraph } => &paragraph.children,
-            BlockType::Quote { ref quote } => &quote.children,
-            _ => &None,
-        };
-        let Some(children) = maybe_kids else {
-            return false;
-        };
-        if children.is_empty() {
-            return false;
+    /// Pack one level of blocks into as few requests as will hold them, splitting off
+    /// only the children that can't go along. A request is full when it has 100 blocks
+    /// at the top, 1000 in all, or as many bytes as the API will take. A block too big
+    /// for a request even on its own is left out, and reported.
+    fn plan_level(&mut self, blocks: VecDeque<Block>) -> Level {
+        let mut level = Level::default();
+        let mut batch: Vec<Block> = Vec::new();
+        let (mut total, mut bytes, mut placed) = (0, 0, 0);
+        for block in blocks {
+            let (block, children) = if self.must_split(&block) {
+                if let Some(plan) = self.plan.as_mut() {
+                    plan.splits += 1;
+                }
+                self.split_block(block)
+            } else {
+                (block, None)
+            };
+            let (count, size) = (count_blocks(&block), payload_size(&block));
+            if size > MAX_PAYLOAD_BYTES {
+                let under: usize = children.as_ref().map_or(0, |xs| xs.iter().map(count_blocks).sum());
+                let under = count - 1 + under;
+                let mut detail = format!(
+                    "a {} block of {size} bytes is too big for a request, so it was left out",
+                    block_kind(&block)
+                );
+                if under > 0 {
+                    detail.push_str(format!(" along with the {under} blocks under it").as_str());
+                }
+                self.diagnostics
+                    .push(Diagnostic::new("block", None, Outcome::Truncated(detail)));
+                continue;
+            }
+            if let Some(children) = children {
+                level.descend.push((placed, children));
+            }
+            let full = batch.len() == MAX_CHILDREN
+                || total + count > MAX_BLOCKS_PER_REQUEST
+                || bytes + size > MAX_PAYLOAD_BYTES;
+            if full && !batch.is_empty() {
+                level.batches.push(std::mem::take(&mut batch));
+                (total, bytes) = (0, 0);
+            }
+            batch.push(block);
+            total += count;
+            bytes += size;
+            placed += 1;
         }
-        if nesting == MAX_NESTING {
-            return true;
+        if !batch.is_empty() {
+            level.batches.push(batch);
         }
-        children
-            .iter()
-            .any(|child| PageMaker::block_has_deep_children(nesting + 1, child))
+        level
+    }
+
+    /// Must this block go up without (some of) its children? Notion only returns the ids
+    /// of the top-level blocks in a request, so children that we need to find again
+    /// later have to be top-level blocks in a request of their own.
+    fn must_split(&self, block: &Block) -> bool {
+        block_has_deep_children(0, block)
+            || block_has_too_many_children(block)
+            || count_blocks(block) > MAX_BLOCKS_PER_REQUEST
+            || payload_size(block) > MAX_PAYLOAD_BYTES
+            || self.block_has_deferred(block)
+            || block_children(block).is_some_and(|xs| xs.iter().any(block_has_local_ids))
+    }
+
+    /// Does this block have children waiting on its creation? We need the block's
+    /// id from the API before we can hang them on it.
+    fn block_has_deferred(&self, block: &Block) -> bool {
+        block.id.as_ref().is_some_and(|id| self.deferred.contains_key(id))
+    }
+
+    /// Split a block from its children, including any deferred ones.
+    fn split_block(&mut self, block: Block) -> (Block, Option<VecDeque<Block>>) {
+        let (copy, maybe_children) = split_block_from_children(block);
+        let Some(body) = copy.id.as_ref().and_then(|id| self.deferred.remove(id)) else {
+            return (copy, maybe_children);
+        };
+        let mut children = maybe_children.unwrap_or_default();
+        children.extend(body);
+        (copy, Some(children))
     }
 }
 
@@ -170,13 +498,254 @@ impl PageMaker {
 /// creation function. Unlike that function, this one makes no attempt to work with the
 /// API's limitation. It does, however, do its best to represent the Markdown data with
 /// Notion block and rich text concepts.
+///
+/// Callouts and toggle headings can't hold children in a `Block`, so here the blocks
+/// that belong inside them come right after them instead. `create_page` puts them
+/// inside, where they belong; [`convert_to_json`] shows them there.
 pub fn convert(input: &str) -> Vec<Block> {
+    convert_with(input, &ConvertOptions::default())
+}
+
+/// Just like `convert`, with your choice of conversion options. Callout and toggle
+/// heading bodies follow them, just as they do there.
+pub fn convert_with(input: &str, options: &ConvertOptions) -> Vec<Block> {
+    let Conversion {
+        blocks, mut deferred, ..
+    } = convert_for_upload(input, options);
+    inline_deferred(blocks, &mut deferred)
+}
+
+/// Notion's URL for a specific block on a page.
+fn block_url(page_id: &str, block_id: &str) -> String {
+    format!(
+        "https://www.notion.so/{}#{}",
+        page_id.replace('-', ""),
+        block_id.replace('-', "")
+    )
+}
+
+/// Everything a page maker needs from a Markdown document.
+#[derive(Debug, Default)]
+struct Conversion {
+    blocks: Vec<Block>,
+    /// The children of blocks like callouts, keyed by local id. Notion's callout
+    /// objects can't carry children in a request, so these get appended after creation.
+    deferred: HashMap<String, Vec<Block>>,
+    /// Link targets within the document, mapping fragments to local ids.
+    anchors: HashMap<String, String>,
+    /// Blocks with links to those targets, keyed by local id, without their children.
+    pending: HashMap<String, Block>,
+    diagnostics: Vec<Diagnostic>,
+    front_matter: Option<FrontMatter>,
+}
+
+fn convert_for_upload(input: &str, options: &ConvertOptions) -> Conversion {
     // This function is infallible with the default options.
-    let Ok(tree) = to_mdast(input, &ParseOptions::gfm()) else {
-        return Vec::new();
+    let Ok(mut tree) = to_mdast(input, &parse_options(options)) else {
+        return Conversion::default();
+    };
+    if options.math {
+        restore_currency(&mut tree, input);
+    }
+    let mut state = State::new(options.clone());
+    let mut front_matter = FrontMatter::from_tree(&tree).unwrap_or_else(|unparsed| {
+        state.report(unparsed);
+        None
+    });
+    if let Some(found) = front_matter.as_mut() {
+        state.diagnostics.append(&mut found.diagnostics);
+    }
+    let mut blocks = fit_rich_text(state.render(tree));
+    state
+        .deferred
+        .values_mut()
+        .for_each(|body| *body = fit_rich_text(std::mem::take(body)));
+    let pending = state.tag_internal_links(&mut blocks);
+    Conversion {
+        blocks,
+        diagnostics: state.diagnostics,
+        deferred: state.deferred,
+        anchors: state.anchors,
+        pending,
+        front_matter,
+    }
+}
+
+/// Convert Markdown to Notion blocks, and also report everything in the Markdown that
+/// didn't survive the trip intact: dropped nodes, HTML shown as code, truncated table
+/// rows, and references to things that aren't in the document.
+pub fn convert_with_report(input: &str, options: &ConvertOptions) -> (Vec<Block>, Vec<Diagnostic>) {
+    let Conversion {
+        blocks,
+        mut deferred,
+        diagnostics,
+        ..
+    } = convert_for_upload(input, options);
+    (inline_deferred(blocks, &mut deferred), diagnostics)
+}
+
+/// Like `convert_with_report`, but the blocks come back as Notion's JSON, with the bodies
+/// of callouts and toggle headings inside them as `children`, the way `create_page`
+/// builds the page.
+pub fn convert_to_json(input: &str, options: &ConvertOptions) -> (serde_json::Value, Vec<Diagnostic>) {
+    let Conversion {
+        blocks,
+        mut deferred,
+        diagnostics,
+        ..
+    } = convert_for_upload(input, options);
+    (
+        serde_json::Value::Array(nest_deferred(blocks, &mut deferred)),
+        diagnostics,
+    )
+}
+
+/// GFM, plus front matter so it doesn't turn into a divider and a paragraph, plus math
+/// if you asked for it.
+fn parse_options(options: &ConvertOptions) -> ParseOptions {
+    let mut parse = ParseOptions::gfm();
+    parse.constructs.frontmatter = true;
+    parse.constructs.math_flow = options.math;
+    parse.constructs.math_text = options.math;
+    parse
+}
+
+/// The math parser takes `$5 and $10` to be an equation reading `5 and `. We follow
+/// Pandoc's rule instead: inline math can't start or end with whitespace, and its closing
+/// dollar sign can't be followed by a digit. Anything breaking the rule goes back to
+/// being the text it was in the source.
+fn restore_currency(node: &mut Node, source: &str) {
+    let Some(children) = node.children_mut() else {
+        return;
+    };
+    let mut restored: Vec<Node> = Vec::with_capacity(children.len());
+    for mut child in children.drain(..) {
+        let literal = match child {
+            Node::InlineMath(ref math) => math
+                .position
+                .as_ref()
+                .map(|pos| (pos.start.offset, pos.end.offset))
+                .filter(|(start, end)| !is_inline_math(source, *start, *end))
+                .map(|(start, end)| (source[start..end].to_string(), math.position.clone())),
+            _ => None,
+        };
+        if let Some((value, position)) = literal {
+            child = Node::Text(mdast::Text { value, position });
+        } else {
+            restore_currency(&mut child, source);
+        }
+        // Stitch the text back together so it doesn't turn into a run per fragment.
+        if let (Some(Node::Text(previous)), Node::Text(text)) = (restored.last_mut(), &child) {
+            previous.value.push_str(text.value.as_str());
+            if let (Some(previous), Some(next)) = (previous.position.as_mut(), text.position.as_ref()) {
+                previous.end = next.end.clone();
+            }
+            continue;
+        }
+        restored.push(child);
+    }
+    *children = restored;
+}
+
+/// Whether `source[start..end]`, which the parser says is inline math, really is.
+fn is_inline_math(source: &str, start: usize, end: usize) -> bool {
+    let raw = &source[start..end];
+    let fence = raw.len() - raw.trim_start_matches('$').len();
+    let Some(inner) = raw.get(fence..raw.len().saturating_sub(fence)) else {
+        return false;
     };
-    let mut state = State::new();
-    state.render(tree)
+    !inner.starts_with(char::is_whitespace)
+        && !inner.ends_with(char::is_whitespace)
+        && !source[end..].starts_with(|c: char| c.is_ascii_digit())
+}
+
+/// A plain vector of blocks has no way to say "these blocks belong inside that callout",
+/// so for callers of `convert()` deferred children follow the block they belong to.
+fn inline_deferred(blocks: Vec<Block>, deferred: &mut HashMap<String, Vec<Block>>) -> Vec<Block> {
+    let mut result = Vec::new();
+    for mut block in blocks {
+        if let Some(children) = block_children_mut(&mut block) {
+            let taken = std::mem::take(children);
+            *children = inline_deferred(taken, deferred);
+        }
+        let body = block.id.take().and_then(|id| deferred.remove(&id)).unwrap_or_default();
+        result.push(block);
+        result.extend(inline_deferred(body, deferred));
+    }
+    result
+}
+
+/// Blocks as JSON, with their children, deferred or not, under `children` in their type's
+/// object, the way the API shows them.
+fn nest_deferred(blocks: Vec<Block>, deferred: &mut HashMap<String, Vec<Block>>) -> Vec<serde_json::Value> {
+    blocks
+        .into_iter()
+        .map(|mut block| {
+            let mut children = block_children_slot(&mut block)
+                .and_then(Option::take)
+                .unwrap_or_default();
+            if let Some(body) = block.id.take().and_then(|id| deferred.remove(&id)) {
+                children.extend(body);
+            }
+            // Blocks always serialize.
+            let mut value = serde_json::to_value(&block).unwrap_or_default();
+            let kind = value["type"].as_str().unwrap_or_default().to_owned();
+            if !children.is_empty() {
+                value[kind.as_str()]["children"] = serde_json::Value::Array(nest_deferred(children, deferred));
+            }
+            value
+        })
+        .collect()
+}
+
+/// A piece of inline content. Images are blocks in Notion, so they interrupt the text.
+#[derive(Debug, Clone)]
+enum Inline {
+    Text(Vec<RichText>),
+    /// The image blocks, and what to report if they can't go where they are.
+    Image {
+        blocks: Vec<Block>,
+        dropped: Diagnostic,
+    },
+    /// The end of an HTML paragraph.
+    Break,
+}
+
+/// An open inline HTML tag and the style it puts on everything inside it.
+#[derive(Debug, Clone, Default)]
+struct HtmlFrame {
+    tag: String,
+    style: Annotations,
+    link: Option<String>,
+    script: Option<Script>,
+}
+
+impl HtmlFrame {
+    /// The frame for a tag opened inside this one, if it's a tag we know.
+    fn open(&self, tag: &str, token: &Token) -> Option<HtmlFrame> {
+        let mut frame = HtmlFrame {
+            tag: tag.to_owned(),
+            ..self.clone()
+        };
+        match tag {
+            "b" | "strong" => frame.style.bold = true,
+            "i" | "em" => frame.style.italic = true,
+            "u" | "ins" => frame.style.underline = true,
+            "s" | "del" | "strike" => frame.style.strikethrough = true,
+            "code" | "kbd" => frame.style.code = true,
+            "mark" => frame.style.color = notion_client::objects::rich_text::TextColor::YellowBackground,
+            "sup" => frame.script = Some(Script::Super),
+            "sub" => frame.script = Some(Script::Sub),
+            "a" => {
+                if let Some(href) = token.attr("href").filter(|xs| !xs.is_empty()) {
+                    frame.link = Some(href.to_owned());
+                }
+            }
+            "span" => {}
+            _ => return None,
+        }
+        Some(frame)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -186,6 +755,89 @@ enum ListVariation {
     Ordered,
 }
 
+/// GitHub-style `> [!NOTE]` admonitions, which we turn into callouts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Admonition {
+    Note,
+    Tip,
+    Important,
+    Warning,
+    Caution,
+}
+
+impl Admonition {
+    /// Recognize the marker at the very start of an admonition, returning the kind
+    /// and whatever text follows the marker.
+    fn parse(input: &str) -> Option<(Admonition, &str)> {
+        let rest = input.strip_prefix("[!")?;
+        let (marker, rest) = rest.split_once(']')?;
+        let kind = match marker.to_lowercase().as_str() {
+            "note" | "info" => Admonition::Note,
+            "tip" | "hint" => Admonition::Tip,
+            "important" => Admonition::Important,
+            "warning" => Admonition::Warning,
+            "caution" | "danger" => Admonition::Caution,
+            _ => return None,
+        };
+        let rest = rest.trim_start_matches([' ', '\t']);
+        Some((kind, rest.strip_prefix('\n').unwrap_or(rest)))
+    }
+
+    /// The kind of admonition a callout with this emoji came from.
+    fn from_emoji(emoji: &str) -> Option<Admonition> {
+        [
+            Admonition::Note,
+            Admonition::Tip,
+            Admonition::Important,
+            Admonition::Warning,
+            Admonition::Caution,
+        ]
+        .into_iter()
+        .find(|xs| xs.emoji() == emoji)
+    }
+
+    /// What goes between `[!` and `]`.
+    fn marker(&self) -> &'static str {
+        match self {
+            Admonition::Note => "NOTE",
+            Admonition::Tip => "TIP",
+            Admonition::Important => "IMPORTANT",
+            Admonition::Warning => "WARNING",
+            Admonition::Caution => "CAUTION",
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            Admonition::Note => "Note",
+            Admonition::Tip => "Tip",
+            Admonition::Important => "Important",
+            Admonition::Warning => "Warning",
+            Admonition::Caution => "Caution",
+        }
+    }
+
+    fn emoji(&self) -> &'static str {
+        match self {
+            Admonition::Note => "ℹ️",
+            Admonition::Tip => "💡",
+            Admonition::Important => "❗",
+            Admonition::Warning => "⚠️",
+            Admonition::Caution => "🛑",
+        }
+    }
+
+    fn color(&self) -> TextColor {
+        match self {
+            Admonition::Note => TextColor::BlueBackground,
+            Admonition::Tip => TextColor::GreenBackground,
+            Admonition::Important => TextColor::PurpleBackground,
+            Admonition::Warning => TextColor::YellowBackground,
+            Admonition::Caution => TextColor::RedBackground,
+        }
+    }
+}
+
 /// We need to track a little state when we're rendering lists, which can be nested.
 /// We also need to gather up link and image reference definitions so we can substitute
 /// in the full links when we encounter them in the markup.
@@ -195,54 +847,198 @@ struct State {
     ordered_start: u32,
     links: HashMap<String, String>,
     images: HashMap<String, mdast::Image>,
+    /// Children for blocks whose Notion representation can't hold them, keyed by local id.
+    deferred: HashMap<String, Vec<Block>>,
+    /// How many local ids we've handed out.
+    local_ids: usize,
+    /// In-document link targets, mapping fragments to local ids.
+    anchors: HashMap<String, String>,
+    /// The footnotes we've seen defined, by identifier.
+    footnote_ids: HashSet<String>,
+    /// Rendered footnotes, which all go in a section at the end of the page.
+    footnotes: Vec<Block>,
+    /// Slugs for every heading in the document, in document order.
+    heading_slugs: VecDeque<String>,
+    /// The fragments of every in-document link, like `setup` for `[see setup](#setup)`.
+    link_fragments: HashSet<String>,
+    /// Everything we had to drop or mangle along the way.
+    diagnostics: Vec<Diagnostic>,
+    options: ConvertOptions,
+    /// Whether every heading moves up a level; see `HeadingPolicy::ShiftUp`.
+    shift_headings: bool,
+    /// Whether the document asks for a table of contents with a `[TOC]` line.
+    toc_marker: bool,
 }
 
 impl State {
-    pub fn new() -> State {
+    pub fn new(options: ConvertOptions) -> State {
         State {
             list: ListVariation::None,
             ordered_start: 1,
             links: HashMap::new(),
             images: HashMap::new(),
+            deferred: HashMap::new(),
+            local_ids: 0,
+            anchors: HashMap::new(),
+            footnote_ids: HashSet::new(),
+            footnotes: Vec::new(),
+            heading_slugs: VecDeque::new(),
+            link_fragments: HashSet::new(),
+            diagnostics: Vec::new(),
+            options,
+            shift_headings: false,
+            toc_marker: false,
+        }
+    }
+
+    fn report(&mut self, diagnostic: Diagnostic) {
+        self.diagnostics.push(diagnostic);
+    }
+
+    /// Make sure this block has a local id, so we can find it again after it's created.
+    fn ensure_local_id(&mut self, block: &mut Block) -> String {
+        if let Some(ref id) = block.id {
+            return id.clone();
+        }
+        let id = format!("{LOCAL_ID_PREFIX}{}", self.local_ids);
+        self.local_ids += 1;
+        block.id = Some(id.clone());
+        id
+    }
+
+    /// Give a block a local id and hold its children until the block exists in Notion.
+    fn defer_children(&mut self, block: &mut Block, children: Vec<Block>) {
+        if children.is_empty() {
+            return;
         }
+        let id = self.ensure_local_id(block);
+        self.deferred.insert(id, children);
     }
 
     /// The function to call to do the work. All of this is infallible.
     pub fn render(&mut self, tree: Node) -> Vec<Block> {
-        if let Some(children) = tree.children() {
-            self.render_nodes(children)
-        } else {
-            Vec::new()
+        let Some(children) = tree.children() else {
+            return Vec::new();
+        };
+        self.collect_definitions(children);
+        self.shift_headings = self.options.headings == HeadingPolicy::ShiftUp
+            && !children
+                .iter()
+                .any(|xs| matches!(xs, Node::Heading(heading) if heading.depth == 1));
+        let headings = self.heading_slugs.len();
+        let mut blocks = self.render_nodes(children);
+        if !self.toc_marker && self.options.auto_toc.is_some_and(|most| headings > most) {
+            blocks.insert(0, toc_block());
+        }
+        if !self.footnotes.is_empty() {
+            let heading_2 = HeadingsValue {
+                rich_text: State::split_text_at_api_limit("Footnotes".to_string(), Annotations::default(), None),
+                ..Default::default()
+            };
+            blocks.push(Block {
+                block_type: BlockType::Heading2 { heading_2 },
+                ..Default::default()
+            });
+            blocks.append(&mut self.footnotes);
+        }
+        blocks
+    }
+
+    /// Find every block with an in-document link in its own text and give it a local id,
+    /// so it can be patched once the link targets exist. Returns copies of those blocks
+    /// without their children, ready to be sent as updates.
+    fn tag_internal_links(&mut self, blocks: &mut [Block]) -> HashMap<String, Block> {
+        let mut pending = HashMap::new();
+        self.tag_blocks(blocks, &mut pending);
+        let mut keys: Vec<String> = self.deferred.keys().cloned().collect();
+        keys.sort();
+        for key in keys {
+            if let Some(mut body) = self.deferred.remove(&key) {
+                self.tag_blocks(body.as_mut_slice(), &mut pending);
+                self.deferred.insert(key, body);
+            }
         }
+        pending
+    }
+
+    fn tag_blocks(&mut self, blocks: &mut [Block], pending: &mut HashMap<String, Block>) {
+        blocks.iter_mut().for_each(|block| {
+            if block_rich_text_mut(block)
+                .into_iter()
+                .any(|run| internal_fragment(run).is_some())
+            {
+                let id = self.ensure_local_id(block);
+                let (mut copy, _children) = split_block_from_children(block.clone());
+                copy.id = None;
+                pending.insert(id, copy);
+            }
+            if let Some(children) = block_children_mut(block) {
+                self.tag_blocks(children.as_mut_slice(), pending);
+            }
+        });
     }
 
     /// Render the passed-in vector of nodes.
+    /// HTML blocks can wrap the nodes that follow them, like `<details>` does, so they
+    /// get to look at their siblings and say how many of them they used up. So can
+    /// headings, when deep headings are toggles.
     fn render_nodes(&mut self, nodelist: &[Node]) -> Vec<Block> {
-        self.collect_definitions(nodelist);
-        nodelist
-            .iter()
-            .flat_map(|xs| self.render_node(xs))
-            .collect::<Vec<Block>>()
+        let mut blocks = Vec::new();
+        let mut idx = 0;
+        while idx < nodelist.len() {
+            if let Node::Html(_) = nodelist[idx] {
+                let (rendered, consumed) = self.render_html(&nodelist[idx..]);
+                blocks.extend(rendered);
+                idx += consumed;
+            } else if let Node::Heading(ref heading) = nodelist[idx] {
+                let (rendered, consumed) = self.render_heading_section(heading, &nodelist[idx + 1..]);
+                blocks.extend(rendered);
+                idx += consumed;
+            } else {
+                blocks.extend(self.render_node(&nodelist[idx]));
+                idx += 1;
+            }
+        }
+        blocks
     }
 
     /// Collect definitions for images and links, which can be referred to
-    /// many times in a single markdown document.
+    /// many times in a single markdown document. Definitions are document-wide
+    /// no matter how deeply they're nested, so we walk the whole tree once up front.
+    /// While we're walking the whole tree, we also note heading slugs and in-document links.
     fn collect_definitions(&mut self, nodelist: &[Node]) {
-        let mut links = HashMap::new();
-        let mut images = HashMap::new();
-
-        nodelist.iter().for_each(|xs| match xs {
-            Node::Image(image) => {
-                images.insert(image.alt.clone(), image.clone());
+        nodelist.iter().for_each(|xs| {
+            match xs {
+                Node::Heading(_) => {
+                    let slug = heading_slug(xs.to_string().as_str(), &self.heading_slugs);
+                    self.heading_slugs.push_back(slug);
+                }
+                Node::Link(link) => {
+                    if let Some(fragment) = link.url.strip_prefix('#') {
+                        self.link_fragments.insert(fragment.to_lowercase());
+                    }
+                }
+                Node::Image(image) => {
+                    self.images.insert(image.alt.clone(), image.clone());
+                }
+                Node::Definition(definition) => {
+                    self.links.insert(definition.identifier.clone(), definition.url.clone());
+                    if let Some(fragment) = definition.url.strip_prefix('#') {
+                        self.link_fragments.insert(fragment.to_lowercase());
+                    }
+                }
+                Node::FootnoteDefinition(footnote) => {
+                    self.footnote_ids.insert(footnote.identifier.clone());
+                }
+                Node::Paragraph(para) if is_toc_marker(para) => {
+                    self.toc_marker = true;
+                }
+                _ => {}
             }
-            Node::Definition(definition) => {
-                links.insert(definition.identifier.clone(), definition.url.clone());
+            if let Some(children) = xs.children() {
+                self.collect_definitions(children);
             }
-            _ => {}
         });
-
-        self.links = links;
-        self.images = images;
     }
 
     /// Render a node that becomes either a single Notion block or a vec of them.
@@ -253,7 +1049,7 @@ impl State {
             Node::BlockQuote(quote) => self.render_quote(quote),
             Node::FootnoteDefinition(footnote) => self.render_footnote(footnote),
             Node::List(list) => self.begin_list(list),
-            Node::Html(html) => self.render_html(html),
+            Node::Html(_) => self.render_html(std::slice::from_ref(node)).0,
             Node::Image(image) => self.render_image(image),
             Node::ImageReference(imgref) => self.render_image_ref(imgref),
             Node::Code(code) => self.render_code(code),
@@ -264,49 +1060,193 @@ impl State {
             Node::ThematicBreak(div) => self.render_divider(div),
             Node::ListItem(list_item) => self.render_list_item(list_item),
             Node::Paragraph(paragraph) => self.render_paragraph(paragraph),
-            // All unhandled node types are deliberately skipped.
-            _ => Vec::new(),
+            // We've already taken what we need from these.
+            Node::Definition(_) | Node::Yaml(_) | Node::Toml(_) => Vec::new(),
+            // All other node types are deliberately skipped, but we say so.
+            _ => {
+                self.report(Diagnostic::for_node(node, Outcome::Dropped));
+                Vec::new()
+            }
         }
     }
 
-    /// Render a node type that becomes Notion rich text.
-    fn render_text_node(&self, node: &Node) -> Option<Vec<RichText>> {
+    /// Render phrasing content, carrying the annotations and link target of every
+    /// enclosing node down to the text runs. This is how `**bold _and italic_**`
+    /// ends up with a run that is both bold and italic.
+    fn render_styled(&mut self, node: &Node, style: &Annotations, link: Option<&str>) -> Option<Vec<RichText>> {
         match node {
-            Node::Delete(deletion) => Some(self.render_deletion(deletion)),
-            Node::Emphasis(emphasized) => Some(self.render_emphasized(emphasized)),
+            Node::Delete(deletion) => Some(self.render_deletion(deletion, style, link)),
+            Node::Emphasis(emphasized) => Some(self.render_emphasized(emphasized, style, link)),
             Node::FootnoteReference(reference) => Some(vec![self.render_noteref(reference)]),
-            Node::InlineCode(inline) => Some(vec![self.render_inline_code(inline)]),
-            Node::InlineMath(math) => Some(vec![self.render_inline_math(math)]),
-            Node::Link(link) => Some(vec![self.render_link(link)]),
-            Node::LinkReference(linkref) => Some(vec![self.render_linkref(linkref)]),
-            Node::Strong(strong) => Some(self.render_strong(strong)),
-            Node::Text(text) => Some(self.render_text(text)),
-            _ => None,
+            Node::InlineCode(inline) => Some(self.render_inline_code(inline, style, link)),
+            Node::InlineMath(math) => Some(vec![self.render_inline_math(math, style)]),
+            Node::Link(mdlink) => Some(self.render_link(mdlink, style)),
+            Node::LinkReference(linkref) => Some(self.render_linkref(linkref, style)),
+            Node::Strong(strong) => Some(self.render_strong(strong, style, link)),
+            Node::Text(text) => Some(self.render_text(text, style, link)),
+            // Hard breaks, from two trailing spaces or a trailing backslash, are newlines.
+            Node::Break(_) => Some(State::split_text_at_api_limit("\n".to_owned(), style.clone(), link)),
+            _ => {
+                self.report(Diagnostic::for_node(node, Outcome::Dropped));
+                None
+            }
         }
     }
 
     // Repeat yourself to find patterns, I say, doggedly.
 
     /// Render plain text.
-    fn render_text(&self, input: &mdast::Text) -> Vec<RichText> {
-        let annotations = Annotations { ..Default::default() };
-        State::split_text_at_api_limit(input.value.clone(), annotations)
+    fn render_text(&self, input: &mdast::Text, style: &Annotations, link: Option<&str>) -> Vec<RichText> {
+        State::split_text_at_api_limit(input.value.clone(), style.clone(), link)
     }
 
-    /// Convenience for turning a text range into a rich text blob given a style annotation.
-    fn make_into_rich_text(children: &[Node], style: Annotations) -> Vec<RichText> {
-        let content: String = children
-            .iter()
-            .filter_map(|xs| match xs {
-                Node::Text(ref t) => Some(t.value.clone()),
-                _ => None,
-            })
-            .collect::<Vec<String>>()
-            .join("");
-        State::split_text_at_api_limit(content, style)
+    /// Convenience for turning a node range into rich text runs given the style
+    /// and link target inherited from the parent. Images can't go in rich text.
+    fn make_into_rich_text(&mut self, children: &[Node], style: &Annotations, link: Option<&str>) -> Vec<RichText> {
+        let pieces = self.render_inline(children, style, link);
+        self.inline_text(pieces)
+    }
+
+    /// Render phrasing content that may have images and inline HTML mixed in. Inline
+    /// HTML tags arrive as separate sibling nodes, so this is where we keep track of
+    /// which ones are open.
+    fn render_inline(&mut self, children: &[Node], style: &Annotations, link: Option<&str>) -> Vec<Inline> {
+        let base = HtmlFrame {
+            tag: String::new(),
+            style: style.clone(),
+            link: link.map(|xs| xs.to_owned()),
+            script: None,
+        };
+        let mut frames = vec![base];
+        let mut pieces = Vec::new();
+        for child in children.iter() {
+            match child {
+                Node::Html(html) => {
+                    let tokens = html::tokenize(html.value.as_str());
+                    if self.options.html != HtmlPolicy::Interpret
+                        || !self.render_html_tokens(child, &tokens, &mut frames, &mut pieces)
+                    {
+                        self.report(Diagnostic::for_node(child, Outcome::Dropped));
+                    }
+                }
+                Node::Image(image) => pieces.push(Inline::Image {
+                    blocks: self.render_image(image),
+                    dropped: Diagnostic::for_node(child, Outcome::Dropped),
+                }),
+                Node::ImageReference(imgref) => pieces.push(Inline::Image {
+                    blocks: self.render_image_ref(imgref),
+                    dropped: Diagnostic::for_node(child, Outcome::Dropped),
+                }),
+                _ => {
+                    let frame = frames.last().unwrap_or(&frames[0]).clone();
+                    let runs = self
+                        .render_styled(child, &frame.style, frame.link.as_deref())
+                        .unwrap_or_default();
+                    let runs = self.apply_script(child, runs, frame.script);
+                    pieces.push(Inline::Text(runs));
+                }
+            }
+        }
+        pieces
+    }
+
+    /// Interpret the tags we understand. Returns false if there were any we didn't.
+    /// The first frame is the enclosing style and never gets popped.
+    fn render_html_tokens(
+        &mut self,
+        node: &Node,
+        tokens: &[Token],
+        frames: &mut Vec<HtmlFrame>,
+        pieces: &mut Vec<Inline>,
+    ) -> bool {
+        let mut understood = true;
+        for token in tokens.iter() {
+            let current = frames.last().unwrap_or(&frames[0]).clone();
+            match token {
+                Token::Comment => {}
+                Token::Text(text) => {
+                    let text = match current.script.map(|xs| xs.apply(text)) {
+                        Some(Ok(scripted)) => scripted,
+                        Some(Err(detail)) => {
+                            self.report(Diagnostic::for_node(node, Outcome::Degraded(detail)));
+                            text.clone()
+                        }
+                        None => text.clone(),
+                    };
+                    let runs = State::split_text_at_api_limit(text, current.style, current.link.as_deref());
+                    pieces.push(Inline::Text(runs));
+                }
+                Token::Open { name, .. } if name == "br" => {
+                    let runs = State::split_text_at_api_limit("\n".to_owned(), current.style, current.link.as_deref());
+                    pieces.push(Inline::Text(runs));
+                }
+                Token::Open { name, .. } if name == "img" => {
+                    let src = token.attr("src").unwrap_or_default();
+                    let alt = token.attr("alt").unwrap_or_default();
+                    let blocks = self.image_blocks(src, alt, ("html", node.position()));
+                    let dropped = Diagnostic::for_node(node, Outcome::Dropped);
+                    pieces.push(Inline::Image { blocks, dropped });
+                }
+                Token::Open { name, .. } if name == "p" => pieces.push(Inline::Break),
+                Token::Open { name, .. } => {
+                    if let Some(mut frame) = current.open(name.as_str(), token) {
+                        if name == "a" && frame.link != current.link {
+                            frame.link = frame
+                                .link
+                                .and_then(|url| self.link_url(url.as_str(), "html", node.position()));
+                        }
+                        frames.push(frame);
+                    } else {
+                        understood = false;
+                    }
+                }
+                Token::Close(name) => {
+                    if let Some(idx) = frames.iter().rposition(|xs| &xs.tag == name).filter(|idx| *idx > 0) {
+                        frames.truncate(idx);
+                    } else if name == "p" {
+                        pieces.push(Inline::Break);
+                    } else if !matches!(name.as_str(), "br" | "img") {
+                        understood = false;
+                    }
+                }
+            }
+        }
+        understood
+    }
+
+    /// Put text inside `<sup>` or `<sub>` into that script, saying so when it can't be.
+    fn apply_script(&mut self, node: &Node, mut runs: Vec<RichText>, script: Option<Script>) -> Vec<RichText> {
+        let Some(script) = script else {
+            return runs;
+        };
+        for run in runs.iter_mut() {
+            if let RichText::Text { text, plain_text, .. } = run {
+                match script.apply(text.content.as_str()) {
+                    Ok(scripted) => {
+                        text.content = scripted.clone();
+                        *plain_text = Some(scripted);
+                    }
+                    Err(detail) => self.report(Diagnostic::for_node(node, Outcome::Degraded(detail))),
+                }
+            }
+        }
+        runs
+    }
+
+    /// Just the text. Images have nowhere to go, so we say we dropped them.
+    fn inline_text(&mut self, pieces: Vec<Inline>) -> Vec<RichText> {
+        let mut runs = Vec::new();
+        for piece in pieces {
+            match piece {
+                Inline::Text(text) => runs.extend(text),
+                Inline::Image { dropped, .. } => self.report(dropped),
+                Inline::Break => {}
+            }
+        }
+        runs
     }
 
-    fn split_text_at_api_limit(mut content: String, style: Annotations) -> Vec<RichText> {
+    fn split_text_at_api_limit(mut content: String, style: Annotations, link: Option<&str>) -> Vec<RichText> {
         let mut results: Vec<RichText> = Vec::new();
         while content.len() > 2000 {
             let mut split_point = 2000;
@@ -316,160 +1256,213 @@ impl State {
             let (first, last) = content.split_at(split_point);
             let text = Text {
                 content: first.to_owned(),
-                link: None,
+                link: link.map(|url| Link { url: url.to_owned() }),
             };
             results.push(RichText::Text {
                 text,
                 annotations: Some(style.clone()),
                 plain_text: Some(first.to_owned()),
-                href: None,
+                href: link.map(str::to_owned),
             });
             content = last.to_string();
         }
 
         let text = Text {
             content: content.clone(),
-            link: None,
+            link: link.map(|url| Link { url: url.to_owned() }),
         };
         results.push(RichText::Text {
             text,
             annotations: Some(style),
             plain_text: Some(content),
-            href: None,
+            href: link.map(str::to_owned),
         });
 
         results
     }
 
-    fn render_strong(&self, strong: &mdast::Strong) -> Vec<RichText> {
+    fn render_strong(&mut self, strong: &mdast::Strong, style: &Annotations, link: Option<&str>) -> Vec<RichText> {
         let annotations = Annotations {
             bold: true,
-            ..Default::default()
+            ..style.clone()
         };
-        State::make_into_rich_text(strong.children.as_slice(), annotations)
+        self.make_into_rich_text(strong.children.as_slice(), &annotations, link)
     }
 
-    fn render_emphasized(&self, emphasized: &mdast::Emphasis) -> Vec<RichText> {
+    fn render_emphasized(
+        &mut self,
+        emphasized: &mdast::Emphasis,
+        style: &Annotations,
+        link: Option<&str>,
+    ) -> Vec<RichText> {
         let annotations = Annotations {
             italic: true,
-            ..Default::default()
+            ..style.clone()
         };
-        State::make_into_rich_text(emphasized.children.as_slice(), annotations)
+        self.make_into_rich_text(emphasized.children.as_slice(), &annotations, link)
     }
 
-    fn render_deletion(&self, strike: &mdast::Delete) -> Vec<RichText> {
+    fn render_deletion(&mut self, strike: &mdast::Delete, style: &Annotations, link: Option<&str>) -> Vec<RichText> {
         let annotations = Annotations {
             strikethrough: true,
-            ..Default::default()
+            ..style.clone()
         };
-        State::make_into_rich_text(strike.children.as_slice(), annotations)
+        self.make_into_rich_text(strike.children.as_slice(), &annotations, link)
     }
 
-    fn render_link(&self, mdlink: &mdast::Link) -> RichText {
-        let content: String = mdlink
-            .children
-            .iter()
-            .filter_map(|xs| match xs {
-                Node::Text(ref t) => Some(t.value.clone()),
-                _ => None,
-            })
-            .collect::<Vec<String>>()
-            .join("");
-
+    /// Links don't nest, so whatever link we were inside of is replaced by this one.
+    fn render_link(&mut self, mdlink: &mdast::Link, style: &Annotations) -> Vec<RichText> {
         let url = if let Some(u) = self.links.get(&mdlink.url) {
             u.clone()
         } else {
             mdlink.url.clone()
         };
+        if let Some(target) = self.missing_fragment(url.as_str()) {
+            self.report(Diagnostic::new("link", mdlink.position.as_ref(), target));
+        }
+        let at = mdlink.position.as_ref();
+        self.render_link_to(mdlink.children.as_slice(), style, url.as_str(), ("link", at))
+    }
 
-        let link = Link { url: url.clone() };
-        let text = Text {
-            content: content.clone(),
-            link: Some(link),
+    /// A reference with no definition has nowhere to go, so it's plain text.
+    fn render_linkref(&mut self, linkref: &mdast::LinkReference, style: &Annotations) -> Vec<RichText> {
+        let Some(url) = self.links.get(&linkref.identifier).cloned() else {
+            let missing = Outcome::Unresolved(format!("definition [{}]", linkref.identifier));
+            self.report(Diagnostic::new("linkReference", linkref.position.as_ref(), missing));
+            return self.make_into_rich_text(linkref.children.as_slice(), style, None);
         };
-        RichText::Text {
-            text,
-            annotations: None,
-            plain_text: Some(content),
-            href: Some(url),
+        if let Some(target) = self.missing_fragment(url.as_str()) {
+            self.report(Diagnostic::new("linkReference", linkref.position.as_ref(), target));
         }
+        let at = linkref.position.as_ref();
+        self.render_link_to(linkref.children.as_slice(), style, url.as_str(), ("linkReference", at))
     }
 
-    fn render_linkref(&self, linkref: &mdast::LinkReference) -> RichText {
-        let content: String = linkref
-            .children
-            .iter()
-            .filter_map(|xs| match xs {
-                Node::Text(ref t) => Some(t.value.clone()),
-                _ => None,
-            })
-            .collect::<Vec<String>>()
-            .join("");
-
-        let url = if let Some(u) = self.links.get(&linkref.identifier) {
-            u.clone()
+    /// Is this a link to a heading that isn't in the document?
+    fn missing_fragment(&self, url: &str) -> Option<Outcome> {
+        let fragment = url.strip_prefix('#')?.to_lowercase();
+        if self.heading_slugs.contains(&fragment) {
+            None
         } else {
-            linkref.identifier.clone()
-        };
+            Some(Outcome::Unresolved(format!("heading #{fragment}")))
+        }
+    }
 
-        let link = Link { url: url.clone() };
-        let text = Text {
-            content: content.clone(),
-            link: Some(link),
+    /// Notion won't accept `#fragment` as a link. Links to headings in this document keep
+    /// the fragment in `href` until the page maker can point them at the heading's block.
+    /// Links to fragments that don't exist here are plain text, and so are links Notion
+    /// won't take.
+    fn render_link_to(
+        &mut self,
+        children: &[Node],
+        style: &Annotations,
+        url: &str,
+        (node, position): (&'static str, Option<&Position>),
+    ) -> Vec<RichText> {
+        let Some(fragment) = url.strip_prefix('#') else {
+            let url = self.link_url(url, node, position);
+            return self.make_into_rich_text(children, style, url.as_deref());
         };
-        RichText::Text {
-            text,
-            annotations: None,
-            plain_text: Some(content),
-            href: Some(url),
+        let fragment = fragment.to_lowercase();
+        let mut runs = self.make_into_rich_text(children, style, None);
+        if self.heading_slugs.contains(&fragment) {
+            runs.iter_mut().for_each(|run| {
+                if let RichText::Text { href, .. } = run {
+                    *href = Some(format!("#{fragment}"));
+                }
+            });
         }
+        runs
     }
 
-    fn render_inline_code(&self, inline: &mdast::InlineCode) -> RichText {
-        let text = Text {
-            content: inline.value.clone(),
-            link: None,
-        };
+    /// The URL a link should point to, after the caller's rewriting, if any, and after
+    /// making sure Notion will take it. `None` means the link has to go.
+    fn link_url(&mut self, url: &str, node: &'static str, position: Option<&Position>) -> Option<String> {
+        let url = self.rewritten_link(url);
+        let checked = urls::check_link(url.as_str(), self.options.base_url.as_deref());
+        self.accept_url(url.as_str(), checked, node, position)
+    }
+
+    /// The URL after the caller's rewriting, if any.
+    fn rewritten_link(&self, url: &str) -> String {
+        match self.options.rewrite_link {
+            Some(ref rewrite) => rewrite(url),
+            None => url.to_owned(),
+        }
+    }
+
+    /// Images need absolute web URLs. Relative ones resolve against the image base, or
+    /// the base URL if there's no image base.
+    fn image_url(&mut self, url: &str, node: &'static str, position: Option<&Position>) -> Option<String> {
+        let base = self.options.image_base.as_deref().or(self.options.base_url.as_deref());
+        let checked = urls::check_image(url, base);
+        self.accept_url(url, checked, node, position)
+    }
+
+    /// Report any change we had to make to a URL.
+    fn accept_url(
+        &mut self,
+        original: &str,
+        checked: Checked,
+        node: &'static str,
+        position: Option<&Position>,
+    ) -> Option<String> {
+        match checked {
+            Checked::Fine(url) => Some(url),
+            Checked::Changed(url) => {
+                let detail = format!("`{original}` became `{url}`");
+                self.report(Diagnostic::new(node, position, Outcome::Rewritten(detail)));
+                Some(url)
+            }
+            Checked::Rejected(reason) => {
+                self.report(Diagnostic::new(node, position, Outcome::Unlinked(reason)));
+                None
+            }
+        }
+    }
+
+    fn render_inline_code(&self, inline: &mdast::InlineCode, style: &Annotations, link: Option<&str>) -> Vec<RichText> {
         let annotations = Annotations {
             code: true,
-            ..Default::default()
+            ..style.clone()
         };
-        RichText::Text {
-            text,
-            annotations: Some(annotations),
-            plain_text: Some(inline.value.clone()),
-            href: None,
-        }
+        State::split_text_at_api_limit(inline.value.clone(), annotations, link)
     }
 
-    fn render_inline_math(&self, math: &mdast::InlineMath) -> RichText {
+    /// Notion equations can't be links, so an equation inside a link keeps only its styling.
+    fn render_inline_math(&self, math: &mdast::InlineMath, style: &Annotations) -> RichText {
         let equation = Equation {
             expression: math.value.clone(),
         };
-        let annotations = Annotations {
-            code: true,
-            ..Default::default()
-        };
-
         RichText::Equation {
             equation,
-            annotations,
+            annotations: style.clone(),
             plain_text: math.value.clone(),
             href: None,
         }
     }
 
-    fn render_quote(&self, quote: &mdast::BlockQuote) -> Vec<Block> {
-        let rich_text: Vec<RichText> = quote
-            .children
-            .iter()
-            .filter_map(|xs| self.render_text_node(xs))
-            .flatten()
-            .collect();
+    /// The first paragraph of a block quote becomes the quote's own text. Everything
+    /// after that, whether more paragraphs, lists, or code, nests inside the quote.
+    fn render_quote(&mut self, quote: &mdast::BlockQuote) -> Vec<Block> {
+        if let Some(blocks) = self.render_admonition(quote) {
+            return blocks;
+        }
+        let mut children: VecDeque<Node> = VecDeque::from(quote.children.clone());
+        let rich_text: Vec<RichText> = match children.front() {
+            Some(Node::Paragraph(paragraph)) => {
+                let rich_text = self.make_into_rich_text(paragraph.children.as_slice(), &Annotations::default(), None);
+                children.pop_front();
+                rich_text
+            }
+            _ => Vec::new(),
+        };
+
+        let block_kids: Vec<Block> = self.render_nodes(&Vec::from(children));
         let quote = QuoteValue {
             rich_text,
             color: TextColor::Default,
-            children: None,
+            children: if block_kids.is_empty() { None } else { Some(block_kids) },
         };
         vec![Block {
             block_type: BlockType::Quote { quote },
@@ -477,15 +1470,69 @@ impl State {
         }]
     }
 
-    fn render_footnote(&self, footnote: &mdast::FootnoteDefinition) -> Vec<Block> {
-        let rich_text = footnote
-            .children
-            .iter()
-            .filter_map(|xs| self.render_text_node(xs))
-            .flatten()
-            .collect();
+    /// A block quote that opens with `[!NOTE]`, `[!WARNING]`, and friends becomes a callout.
+    /// The text after the marker is the callout's text; everything else in the quote goes
+    /// inside the callout.
+    fn render_admonition(&mut self, quote: &mdast::BlockQuote) -> Option<Vec<Block>> {
+        let mut children: VecDeque<Node> = VecDeque::from(quote.children.clone());
+        let Some(Node::Paragraph(first)) = children.pop_front() else {
+            return None;
+        };
+        let mut first = first.clone();
+        let Some(Node::Text(marker)) = first.children.first_mut() else {
+            return None;
+        };
+        let (kind, rest) = Admonition::parse(marker.value.as_str())?;
+        if rest.is_empty() {
+            first.children.remove(0);
+        } else {
+            marker.value = rest.to_owned();
+        }
+
+        let rich_text: Vec<RichText> = if first.children.is_empty() {
+            let annotations = Annotations {
+                bold: true,
+                ..Default::default()
+            };
+            State::split_text_at_api_limit(kind.label().to_owned(), annotations, None)
+        } else {
+            self.make_into_rich_text(first.children.as_slice(), &Annotations::default(), None)
+        };
+
+        let emoji = Emoji {
+            emoji: kind.emoji().to_string(),
+        };
+        let callout = CalloutValue {
+            rich_text,
+            icon: Icon::Emoji(emoji),
+            color: kind.color(),
+        };
+        let mut block = Block {
+            block_type: BlockType::Callout { callout },
+            ..Default::default()
+        };
+        let body = self.render_nodes(&Vec::from(children));
+        self.defer_children(&mut block, body);
+        Some(vec![block])
+    }
+
+    /// Footnotes become callouts in a section at the end of the page, no matter where
+    /// they were defined. References to them get linked up after the page is created.
+    fn render_footnote(&mut self, footnote: &mdast::FootnoteDefinition) -> Vec<Block> {
+        let mut children: VecDeque<Node> = VecDeque::from(footnote.children.clone());
+        let label = footnote.label.clone().unwrap_or(footnote.identifier.clone());
+        let annotations = Annotations {
+            color: notion_client::objects::rich_text::TextColor::Gray,
+            ..Default::default()
+        };
+        let mut rich_text = State::split_text_at_api_limit(format!("[{label}] "), annotations, None);
+        if let Some(Node::Paragraph(paragraph)) = children.front() {
+            rich_text.extend(self.make_into_rich_text(paragraph.children.as_slice(), &Annotations::default(), None));
+            children.pop_front();
+        }
+
         let emoji = Emoji {
-            emoji: "🗒️".to_string(),
+            emoji: FOOTNOTE_EMOJI.to_string(),
         };
         let icon = notion_client::objects::block::Icon::Emoji(emoji);
         let callout = CalloutValue {
@@ -493,59 +1540,85 @@ impl State {
             icon,
             color: TextColor::Default,
         };
-        vec![Block {
+        let mut block = Block {
             block_type: BlockType::Callout { callout },
             ..Default::default()
-        }]
+        };
+        let id = self.ensure_local_id(&mut block);
+        self.anchors.insert(footnote_fragment(footnote.identifier.as_str()), id);
+        let body = self.render_nodes(&Vec::from(children));
+        self.defer_children(&mut block, body);
+        if self.options.footnotes == FootnoteStyle::InPlace {
+            return vec![block];
+        }
+        self.footnotes.push(block);
+        Vec::new()
     }
 
-    /// Fragment links are a amajor PITA. You _can_ link to blocks, but you have to get their
-    /// ids first, which means they have to be created first. So we're going to punt and make
-    /// this look like a footnote, but not include the link part part of the WWW. How 1992 of us.
+    /// Fragment links are a major PITA. You _can_ link to blocks, but you have to get their
+    /// ids first, which means they have to be created first. So we render the reference
+    /// as plain text with the fragment tucked into `href`, and the page maker turns it
+    /// into a real link once the footnote exists.
     fn render_noteref(&self, noteref: &mdast::FootnoteReference) -> RichText {
         let annotations = Annotations {
             color: notion_client::objects::rich_text::TextColor::Gray,
             ..Default::default()
         };
+        let label = noteref.label.clone().unwrap_or(noteref.identifier.clone());
+        let content = format!("[{label}]");
         let text = Text {
-            content: noteref.identifier.clone(),
+            content: content.clone(),
             link: None,
         };
+        let href = if self.footnote_ids.contains(&noteref.identifier) {
+            Some(format!("#{}", footnote_fragment(noteref.identifier.as_str())))
+        } else {
+            None
+        };
         RichText::Text {
             text,
             annotations: Some(annotations),
-            plain_text: Some(noteref.identifier.clone()),
-            href: None,
+            plain_text: Some(content),
+            href,
         }
     }
 
     fn begin_table(&mut self, intable: &mdast::Table) -> Vec<Block> {
-        let has_row_header = if let Some(_first) = intable.align.first() {
-            // well, this is probably wrong, but I dunno if I am getting this info
-            // with my current markdown parser settings. hrm.
-            true
-        } else {
-            false
-        };
-
-        let children = self.render_nodes(intable.children.as_slice());
-
-        // Now we look at children and find the row with the largest number of
-        // cells. That's our table width.
-
-        // TODO: Rows that are shorter than this need to be padded out.
-        // Who knew markdown was so flexible and Notion so inflexible?
-        // Answer: Anybody who looked at them both.
+        // A GFM table always opens with its header row; there's no row-header concept.
+        let has_column_header = !intable.children.is_empty();
+        let mut children = self.render_nodes(intable.children.as_slice());
 
+        // The delimiter row decides how many columns a GFM table has. If we somehow
+        // don't have one, fall back on the row with the largest number of cells.
         let longest: u32 = children.iter().fold(1, |acc, xs| match &xs.block_type {
             BlockType::TableRow { table_row } => std::cmp::max(acc, table_row.cells.len() as u32),
             _ => acc,
         });
+        let table_width = if intable.align.is_empty() {
+            longest
+        } else {
+            intable.align.len() as u32
+        };
+
+        // Who knew markdown was so flexible and Notion so inflexible? Answer: Anybody
+        // who looked at them both. Notion rejects rows that aren't exactly the width
+        // of the table, so short rows get padded with empty cells and long ones trimmed.
+        let mut truncated = Vec::new();
+        children.iter_mut().zip(intable.children.iter()).for_each(|(xs, row)| {
+            if let BlockType::TableRow { ref mut table_row } = xs.block_type {
+                if table_row.cells.len() > table_width as usize {
+                    let detail = format!("{} cells in a {table_width}-column table", table_row.cells.len());
+                    truncated.push(Diagnostic::for_node(row, Outcome::Truncated(detail)));
+                }
+                table_row.cells.resize(table_width as usize, Vec::new());
+            }
+        });
+        self.diagnostics.extend(truncated);
 
         let table = TableValue {
-            table_width: longest,
-            has_column_header: false,
-            has_row_header,
+            table_width,
+            has_column_header,
+            has_row_header: false,
             children: Some(children),
         };
         vec![Block {
@@ -554,7 +1627,7 @@ impl State {
         }]
     }
 
-    fn table_row(&self, row: &mdast::TableRow) -> Vec<Block> {
+    fn table_row(&mut self, row: &mdast::TableRow) -> Vec<Block> {
         let cells: Vec<Vec<RichText>> = row
             .children
             .iter()
@@ -571,52 +1644,94 @@ impl State {
         }]
     }
 
-    fn table_cell(&self, cell: &mdast::TableCell) -> Vec<RichText> {
-        cell.children
-            .iter()
-            .filter_map(|xs| self.render_text_node(xs))
-            .flatten()
-            .collect()
+    fn table_cell(&mut self, cell: &mdast::TableCell) -> Vec<RichText> {
+        let runs = self.make_into_rich_text(cell.children.as_slice(), &Annotations::default(), None);
+        self.fit_runs(runs, "tableCell", cell.position.as_ref())
     }
 
-    fn render_paragraph(&self, para: &mdast::Paragraph) -> Vec<Block> {
-        let rich_text: Vec<RichText> = para
-            .children
-            .iter()
-            .filter_map(|xs| self.render_text_node(xs))
-            .flatten()
-            .collect();
-        let paragraph = ParagraphValue {
-            rich_text,
-            color: Some(TextColor::Default),
-            children: None,
-        };
-        vec![Block {
-            block_type: BlockType::Paragraph { paragraph },
-            ..Default::default()
-        }]
+    /// Notion takes at most 100 runs in a piece of rich text. Text that can't carry on in
+    /// another block, like a table cell or a caption, loses the rest.
+    fn fit_runs(&mut self, mut runs: Vec<RichText>, node: &'static str, at: Option<&Position>) -> Vec<RichText> {
+        if runs.len() > MAX_RICH_TEXT_RUNS {
+            let detail = format!("{} text runs, and Notion takes {MAX_RICH_TEXT_RUNS}", runs.len());
+            self.report(Diagnostic::new(node, at, Outcome::Truncated(detail)));
+            runs.truncate(MAX_RICH_TEXT_RUNS);
+        }
+        runs
     }
 
-    fn render_code(&self, fenced: &mdast::Code) -> Vec<Block> {
-        let language = if let Some(langstr) = fenced.lang.as_ref() {
-            serde_json::from_str(langstr.as_str()).unwrap_or(Language::PlainText)
+    /// Notion images are blocks, not rich text, so a paragraph with images in it becomes
+    /// the text before each image, the image, and whatever text comes after.
+    fn render_paragraph(&mut self, para: &mdast::Paragraph) -> Vec<Block> {
+        if is_toc_marker(para) {
+            return vec![toc_block()];
+        }
+        if self.options.standalone_links == StandaloneLinks::Preview {
+            if let Some(block) = self.render_standalone_link(para) {
+                return vec![block];
+            }
+        }
+        let pieces = self.render_inline(para.children.as_slice(), &Annotations::default(), None);
+        let blocks = inline_blocks(pieces);
+        if blocks.is_empty() {
+            vec![paragraph_block(Vec::new())]
         } else {
-            Language::PlainText
-        };
+            blocks
+        }
+    }
 
-        let text = Text {
-            content: fenced.value.clone(),
-            link: None,
+    /// A paragraph that's only a URL, written bare or as an autolink, becomes a bookmark,
+    /// video, PDF, or embed. A link with text of its own stays a link.
+    fn render_standalone_link(&mut self, para: &mdast::Paragraph) -> Option<Block> {
+        let mut meaningful = para
+            .children
+            .iter()
+            .filter(|xs| !matches!(xs, Node::Text(text) if text.value.trim().is_empty()));
+        let (Some(Node::Link(link)), None) = (meaningful.next(), meaningful.next()) else {
+            return None;
         };
-        let rich_text = RichText::Text {
-            text,
-            annotations: None,
-            plain_text: Some(fenced.value.clone()),
-            href: None,
+        let without_scheme = |xs: &str| {
+            xs.trim()
+                .trim_start_matches("https://")
+                .trim_start_matches("http://")
+                .to_owned()
         };
+        // Styled link text was put there on purpose, so that link stays in its paragraph.
+        let [Node::Text(text)] = link.children.as_slice() else {
+            return None;
+        };
+        if without_scheme(text.value.as_str()) != without_scheme(link.url.as_str()) {
+            return None;
+        }
+        // A URL Notion won't take is left to the paragraph, which reports it.
+        let url = self.rewritten_link(link.url.as_str());
+        let checked = urls::check_link(url.as_str(), self.options.base_url.as_deref());
+        let preview = match checked {
+            Checked::Fine(ref xs) | Checked::Changed(ref xs) => urls::preview_for(xs)?,
+            Checked::Rejected(_) => return None,
+        };
+        let url = self.accept_url(url.as_str(), checked, "link", link.position.as_ref())?;
+        Some(preview_block(preview, url))
+    }
+
+    /// Long code goes up as one code block per 100 runs, by way of `fit_rich_text`.
+    fn render_code(&mut self, fenced: &mdast::Code) -> Vec<Block> {
+        let language = fenced
+            .lang
+            .as_deref()
+            .map(languages::language_for)
+            .unwrap_or(Language::PlainText);
+        let caption = fenced
+            .meta
+            .as_deref()
+            .and_then(languages::caption_from_meta)
+            .map(|xs| State::split_text_at_api_limit(xs, Annotations::default(), None))
+            .unwrap_or_default();
+        let caption = self.fit_runs(caption, "code", fenced.position.as_ref());
+
         let code = CodeValue {
-            caption: Vec::new(),
-            rich_text: vec![rich_text],
+            caption,
+            rich_text: State::split_text_at_api_limit(fenced.value.clone(), Annotations::default(), None),
             language,
         };
         vec![Block {
@@ -635,8 +1750,127 @@ impl State {
         }]
     }
 
+    /// HTML we understand becomes the Notion equivalent: `<details>` is a toggle, and
+    /// text with inline tags like `<u>` or `<kbd>` is a paragraph. Returns the blocks and
+    /// how many of `siblings` were used up.
+    fn render_html(&mut self, siblings: &[Node]) -> (Vec<Block>, usize) {
+        let node = &siblings[0];
+        let Node::Html(html) = node else {
+            return (Vec::new(), 1);
+        };
+        match self.options.html {
+            HtmlPolicy::Interpret => {}
+            HtmlPolicy::CodeBlock => {
+                self.report(Diagnostic::for_node(node, Outcome::DegradedToCode));
+                return (vec![State::html_code_block(html)], 1);
+            }
+            HtmlPolicy::Drop => {
+                self.report(Diagnostic::for_node(node, Outcome::Dropped));
+                return (Vec::new(), 1);
+            }
+        }
+        let tokens = html::tokenize(html.value.as_str());
+        let mut meaningful = tokens.iter().filter(|xs| !is_blank_token(xs));
+        if matches!(meaningful.next(), Some(Token::Open { name, .. }) if name == "details") {
+            return self.render_details(siblings, tokens);
+        }
+
+        let mut frames = vec![HtmlFrame::default()];
+        let mut pieces = Vec::new();
+        let reported = self.diagnostics.len();
+        if self.render_html_tokens(node, &tokens, &mut frames, &mut pieces) {
+            return (inline_blocks(pieces), 1);
+        }
+        // Whatever we said about its links doesn't matter now.
+        self.diagnostics.truncate(reported);
+        self.report(Diagnostic::for_node(node, Outcome::DegradedToCode));
+        (vec![State::html_code_block(html)], 1)
+    }
+
+    /// A `<details>` element becomes a toggle, with its `<summary>` as the toggle text.
+    /// Markdown inside the element arrives as sibling nodes, up to the node that closes it.
+    fn render_details(&mut self, siblings: &[Node], tokens: Vec<Token>) -> (Vec<Block>, usize) {
+        let node = &siblings[0];
+        let mut rest = tokens
+            .into_iter()
+            .skip_while(|xs| !matches!(xs, Token::Open { name, .. } if name == "details"))
+            .skip(1)
+            .skip_while(is_blank_token)
+            .peekable();
+
+        let mut summary_tokens = Vec::new();
+        if matches!(rest.peek(), Some(Token::Open { name, .. }) if name == "summary") {
+            rest.next();
+            summary_tokens.extend(
+                rest.by_ref()
+                    .take_while(|xs| !matches!(xs, Token::Close(name) if name == "summary")),
+            );
+        }
+        let mut closed_here = false;
+        let body_tokens: Vec<Token> = rest
+            .take_while(|xs| {
+                closed_here = matches!(xs, Token::Close(name) if name == "details");
+                !closed_here
+            })
+            .collect();
+
+        let mut frames = vec![HtmlFrame::default()];
+        let mut pieces = Vec::new();
+        let mut understood = self.render_html_tokens(node, &summary_tokens, &mut frames, &mut pieces);
+        let mut rich_text = self.inline_text(pieces);
+        if is_blank(&rich_text) {
+            rich_text = State::split_text_at_api_limit("Details".to_owned(), Annotations::default(), None);
+        }
+
+        let mut frames = vec![HtmlFrame::default()];
+        let mut pieces = Vec::new();
+        understood &= self.render_html_tokens(node, &body_tokens, &mut frames, &mut pieces);
+        if !understood {
+            self.report(Diagnostic::for_node(node, Outcome::Dropped));
+        }
+        let mut children = inline_blocks(pieces);
+
+        let mut consumed = 1;
+        if !closed_here {
+            // Find the matching close, allowing for toggles inside this one.
+            let mut depth = 0;
+            let end = siblings[1..]
+                .iter()
+                .position(|sibling| {
+                    let Node::Html(html) = sibling else {
+                        return false;
+                    };
+                    html::tokenize(html.value.as_str()).iter().any(|xs| match xs {
+                        Token::Open { name, .. } if name == "details" => {
+                            depth += 1;
+                            false
+                        }
+                        Token::Close(name) if name == "details" => {
+                            depth -= 1;
+                            depth < 0
+                        }
+                        _ => false,
+                    })
+                })
+                .map_or(siblings.len(), |idx| idx + 1);
+            children.extend(self.render_nodes(&siblings[1..end]));
+            consumed = std::cmp::min(end + 1, siblings.len());
+        }
+
+        let toggle = ToggleValue {
+            rich_text,
+            color: TextColor::Default,
+            children: if children.is_empty() { None } else { Some(children) },
+        };
+        let block = Block {
+            block_type: BlockType::Toggle { toggle },
+            ..Default::default()
+        };
+        (vec![block], consumed)
+    }
+
     // This is a hack. There really isn't an equivalent AFAICT.
-    fn render_html(&self, html: &mdast::Html) -> Vec<Block> {
+    fn html_code_block(html: &mdast::Html) -> Block {
         let text = Text {
             content: html.value.clone(),
             link: None,
@@ -652,50 +1886,94 @@ impl State {
             rich_text: vec![rich_text],
             language: Language::PlainText,
         };
-        vec![Block {
+        Block {
             block_type: BlockType::Code { code },
             ..Default::default()
-        }]
+        }
     }
 
     /// Img block pointing to a previously declared image.
-    fn render_image_ref(&self, imgref: &mdast::ImageReference) -> Vec<Block> {
-        if let Some(image) = self.images.get(&imgref.identifier) {
-            self.render_image(image)
+    fn render_image_ref(&mut self, imgref: &mdast::ImageReference) -> Vec<Block> {
+        let url = self
+            .links
+            .get(&imgref.identifier)
+            .or_else(|| self.images.get(&imgref.identifier).map(|xs| &xs.url))
+            .cloned();
+        if let Some(url) = url {
+            self.image_blocks(
+                url.as_str(),
+                imgref.alt.as_str(),
+                ("imageReference", imgref.position.as_ref()),
+            )
         } else {
-            vec![Block {
-                block_type: BlockType::None,
-                ..Default::default()
-            }]
+            let missing = Outcome::Unresolved(format!("definition [{}]", imgref.identifier));
+            self.report(Diagnostic::new("imageReference", imgref.position.as_ref(), missing));
+            Vec::new()
         }
     }
 
-    fn render_image(&self, image: &mdast::Image) -> Vec<Block> {
+    fn render_image(&mut self, image: &mdast::Image) -> Vec<Block> {
+        self.image_blocks(
+            image.url.as_str(),
+            image.alt.as_str(),
+            ("image", image.position.as_ref()),
+        )
+    }
+
+    /// The image block, followed by its alt text as a caption. The client library's image
+    /// block has no caption field, so the caption is a small gray paragraph of its own.
+    /// If Notion won't take the URL, the caption is all there is.
+    fn image_blocks(
+        &mut self,
+        url: &str,
+        alt: &str,
+        (node, position): (&'static str, Option<&Position>),
+    ) -> Vec<Block> {
         // TODO: For now. What we should do is figure out if this is a local image and upload
         // if so and make a local file url.
-        let external = ExternalFile { url: image.url.clone() };
-        let file_type = File::External { external };
-        let image = ImageValue { file_type };
-        vec![Block {
-            block_type: BlockType::Image { image },
-            ..Default::default()
-        }]
+        let mut blocks = Vec::new();
+        if let Some(url) = self.image_url(url, node, position) {
+            let external = ExternalFile { url };
+            let file_type = File::External { external };
+            let image = ImageValue { file_type };
+            blocks.push(Block {
+                block_type: BlockType::Image { image },
+                ..Default::default()
+            });
+        }
+        if !alt.trim().is_empty() {
+            let annotations = Annotations {
+                italic: true,
+                color: notion_client::objects::rich_text::TextColor::Gray,
+                ..Default::default()
+            };
+            let caption = State::split_text_at_api_limit(alt.trim().to_owned(), annotations, None);
+            blocks.push(paragraph_block(caption));
+        }
+        blocks
     }
 
     fn begin_list(&mut self, list: &mdast::List) -> Vec<Block> {
-        let mut state = self.clone();
-        state.list = if list.ordered {
+        // Save the enclosing list's settings so nested lists don't clobber them.
+        let saved = (self.list.clone(), self.ordered_start);
+        self.list = if list.ordered {
             ListVariation::Ordered
         } else {
             ListVariation::Bulleted
         };
         if let Some(start) = list.start {
-            state.ordered_start = start;
+            self.ordered_start = start;
         }
-        state.render_nodes(list.children.as_slice())
+        let blocks = self.render_nodes(list.children.as_slice());
+        (self.list, self.ordered_start) = saved;
+        blocks
     }
 
     fn render_list_item(&mut self, item: &mdast::ListItem) -> Vec<Block> {
+        // GFM task list items can turn up in either kind of list.
+        if let Some(checked) = item.checked {
+            return self.render_todo_li(item, checked);
+        }
         match self.list {
             ListVariation::None => self.rendered_bullet_li(item),
             ListVariation::Bulleted => self.rendered_bullet_li(item),
@@ -703,38 +1981,32 @@ impl State {
         }
     }
 
-    // TODO these two list item functions have a lot in common, you know?
-
-    fn render_numbered_li(&mut self, item: &mdast::ListItem) -> Vec<Block> {
+    /// All list items share a shape: the first paragraph is the item's text, and
+    /// anything after it becomes nested child blocks. Empty items have no children at all.
+    fn list_item_content(&mut self, item: &mdast::ListItem) -> (Vec<RichText>, Option<Vec<Block>>) {
         let mut children: VecDeque<Node> = VecDeque::from(item.children.clone());
         let Some(first) = children.pop_front() else {
             // we can short-circuit. Empty list.
-            let numbered_list_item = NumberedListItemValue {
-                rich_text: Vec::new(),
-                color: TextColor::Default,
-                children: None,
-            };
-            return vec![Block {
-                block_type: BlockType::NumberedListItem { numbered_list_item },
-                ..Default::default()
-            }];
+            return (Vec::new(), None);
         };
 
         let rich_text: Vec<RichText> = match first {
-            Node::Paragraph(paragraph) => paragraph
-                .children
-                .iter()
-                .filter_map(|xs| self.render_text_node(xs))
-                .flatten()
-                .collect(),
+            Node::Paragraph(paragraph) => {
+                self.make_into_rich_text(paragraph.children.as_slice(), &Annotations::default(), None)
+            }
             _ => Vec::new(),
         };
 
         let block_kids: Vec<Block> = self.render_nodes(&Vec::from(children));
+        (rich_text, Some(block_kids))
+    }
+
+    fn render_numbered_li(&mut self, item: &mdast::ListItem) -> Vec<Block> {
+        let (rich_text, children) = self.list_item_content(item);
         let numbered_list_item = NumberedListItemValue {
             rich_text,
             color: TextColor::Default,
-            children: Some(block_kids),
+            children,
         };
         vec![Block {
             block_type: BlockType::NumberedListItem { numbered_list_item },
@@ -743,35 +2015,11 @@ impl State {
     }
 
     fn rendered_bullet_li(&mut self, item: &mdast::ListItem) -> Vec<Block> {
-        let mut children: VecDeque<Node> = VecDeque::from(item.children.clone());
-        let Some(first) = children.pop_front() else {
-            // we can short-circuit. Empty list.
-            let bulleted_list_item = BulletedListItemValue {
-                rich_text: Vec::new(),
-                color: TextColor::Default,
-                children: None,
-            };
-            return vec![Block {
-                block_type: BlockType::BulletedListItem { bulleted_list_item },
-                ..Default::default()
-            }];
-        };
-
-        let rich_text: Vec<RichText> = match first {
-            Node::Paragraph(paragraph) => paragraph
-                .children
-                .iter()
-                .filter_map(|xs| self.render_text_node(xs))
-                .flatten()
-                .collect(),
-            _ => Vec::new(),
-        };
-
-        let block_kids: Vec<Block> = self.render_nodes(&Vec::from(children));
+        let (rich_text, children) = self.list_item_content(item);
         let bulleted_list_item = BulletedListItemValue {
             rich_text,
             color: TextColor::Default,
-            children: Some(block_kids),
+            children,
         };
         vec![Block {
             block_type: BlockType::BulletedListItem { bulleted_list_item },
@@ -779,6 +2027,21 @@ impl State {
         }]
     }
 
+    /// `- [ ] thing` and `- [x] done` become Notion to-do blocks, checked state intact.
+    fn render_todo_li(&mut self, item: &mdast::ListItem, checked: bool) -> Vec<Block> {
+        let (rich_text, children) = self.list_item_content(item);
+        let to_do = ToDoValue {
+            rich_text,
+            checked: Some(checked),
+            color: Some(TextColor::Default),
+            children,
+        };
+        vec![Block {
+            block_type: BlockType::ToDo { to_do },
+            ..Default::default()
+        }]
+    }
+
     fn render_divider(&self, _thematic: &mdast::ThematicBreak) -> Vec<Block> {
         let divider = DividerValue {};
         vec![Block {
@@ -787,34 +2050,431 @@ impl State {
         }]
     }
 
-    fn render_heading(&self, heading: &mdast::Heading) -> Vec<Block> {
-        let rich_text: Vec<RichText> = heading
-            .children
+    /// A heading, and if it's a toggle, everything it holds. Returns the blocks and how
+    /// many of the following siblings it used up.
+    fn render_heading_section(&mut self, heading: &mdast::Heading, following: &[Node]) -> (Vec<Block>, usize) {
+        let mut blocks = self.render_heading(heading);
+        if self.options.headings != HeadingPolicy::Toggle || heading.depth <= 3 {
+            return (blocks, 1);
+        }
+        let end = following
             .iter()
-            .filter_map(|xs| self.render_text_node(xs))
-            .flatten()
-            .collect();
+            .position(|xs| matches!(xs, Node::Heading(next) if next.depth <= heading.depth))
+            .unwrap_or(following.len());
+        let children = self.render_nodes(&following[..end]);
+        // Heading blocks can't carry children in a request, so these go up afterwards.
+        if let Some(block) = blocks.first_mut() {
+            self.defer_children(block, children);
+        }
+        (blocks, end + 1)
+    }
+
+    fn render_heading(&mut self, heading: &mdast::Heading) -> Vec<Block> {
+        let mut rich_text: Vec<RichText> =
+            self.make_into_rich_text(heading.children.as_slice(), &Annotations::default(), None);
+        let depth = if self.shift_headings {
+            heading.depth - 1
+        } else {
+            heading.depth
+        };
 
         let value = HeadingsValue {
-            rich_text,
+            rich_text: rich_text.clone(),
             ..Default::default()
         };
-        let block_type = if heading.depth == 1 {
-            BlockType::Heading1 { heading_1: value }
-        } else if heading.depth == 2 {
-            BlockType::Heading2 { heading_2: value }
-        } else {
-            BlockType::Heading3 { heading_3: value }
+        let block_type = match depth {
+            1 => BlockType::Heading1 { heading_1: value },
+            2 => BlockType::Heading2 { heading_2: value },
+            3 => BlockType::Heading3 { heading_3: value },
+            _ if self.options.headings == HeadingPolicy::Toggle => BlockType::Heading3 {
+                heading_3: HeadingsValue {
+                    is_toggleable: Some(true),
+                    ..value
+                },
+            },
+            _ => {
+                rich_text.iter_mut().for_each(|run| {
+                    if let RichText::Text { annotations, .. } = run {
+                        annotations.get_or_insert_with(Annotations::default).bold = true;
+                    }
+                });
+                let paragraph = ParagraphValue {
+                    rich_text,
+                    color: Some(TextColor::Default),
+                    children: None,
+                };
+                BlockType::Paragraph { paragraph }
+            }
         };
 
-        vec![Block {
+        let mut block = Block {
             block_type,
             ..Default::default()
-        }]
+        };
+        // Headings that are link targets need ids we can find again after they're created.
+        if let Some(slug) = self.heading_slugs.pop_front() {
+            if self.link_fragments.contains(&slug) && !self.anchors.contains_key(&slug) {
+                let id = self.ensure_local_id(&mut block);
+                self.anchors.insert(slug.clone(), id);
+            }
+            // Keep the slug around; links later in the document still need it.
+            self.heading_slugs.push_back(slug);
+        }
+        vec![block]
+    }
+}
+
+/// Does this block nest deeper than a single request may?
+fn block_has_deep_children(nesting: u8, block: &Block) -> bool {
+    let Some(children) = block_children(block) else {
+        return false;
+    };
+    if children.is_empty() {
+        return false;
     }
+    if nesting == MAX_NESTING {
+        return true;
+    }
+    children.iter().any(|child| block_has_deep_children(nesting + 1, child))
+}
+
+/// Does this block, or any block under it, have more direct children than a single
+/// request may carry? Long tables do, and so can long lists nested in other lists.
+fn block_has_too_many_children(block: &Block) -> bool {
+    block_children(block).is_some_and(|xs| xs.len() > MAX_CHILDREN || xs.iter().any(block_has_too_many_children))
+}
+
+/// The children of the block types we generate that can hold them. There are many
+/// block types here that we skip because we are never generating them while
+/// converting from markdown.
+fn block_children(block: &Block) -> Option<&Vec<Block>> {
+    let maybe_kids = match block.block_type {
+        BlockType::BulletedListItem { ref bulleted_list_item } => &bulleted_list_item.children,
+        BlockType::NumberedListItem { ref numbered_list_item } => &numbered_list_item.children,
+        BlockType::Paragraph { ref paragraph } => &paragraph.children,
+        BlockType::Quote { ref quote } => &quote.children,
+        BlockType::ToDo { ref to_do } => &to_do.children,
+        BlockType::Toggle { ref toggle } => &toggle.children,
+        BlockType::Table { ref table } => &table.children,
+        _ => &None,
+    };
+    maybe_kids.as_ref()
+}
+
+/// Does this block, or anything inside it, have a local id?
+fn block_has_local_ids(block: &Block) -> bool {
+    block.id.is_some() || block_children(block).is_some_and(|xs| xs.iter().any(block_has_local_ids))
+}
+
+/// A line asking for a table of contents: `[TOC]`, or GitLab's `[[_TOC_]]`.
+fn is_toc_marker(para: &mdast::Paragraph) -> bool {
+    // `[[_TOC_]]` parses as emphasis inside brackets; any kind of link isn't a marker.
+    let plain = para
+        .children
+        .iter()
+        .all(|xs| matches!(xs, Node::Text(_) | Node::Emphasis(_)));
+    let text = para.children.iter().map(|xs| xs.to_string()).collect::<String>();
+    plain && matches!(text.trim().to_uppercase().as_str(), "[TOC]" | "[[TOC]]")
+}
+
+fn toc_block() -> Block {
+    let table_of_contents = TableOfContentsValue {
+        color: TextColor::Default,
+    };
+    Block {
+        block_type: BlockType::TableOfContents { table_of_contents },
+        ..Default::default()
+    }
+}
+
+/// Notion takes at most 100 runs in a piece of rich text, so a block with more goes up
+/// as several. List items, to-dos, and toggles carry on in paragraphs inside themselves;
+/// anything else carries on in blocks of its own kind right after it. A code block's
+/// caption stays with its first piece.
+fn fit_rich_text(blocks: Vec<Block>) -> Vec<Block> {
+    blocks.into_iter().flat_map(fit_block_text).collect()
+}
+
+fn fit_block_text(mut block: Block) -> Vec<Block> {
+    if let Some(children) = block_children_mut(&mut block) {
+        *children = fit_rich_text(std::mem::take(children));
+    }
+    let Some(runs) = block_text_slot(&mut block).filter(|xs| xs.len() > MAX_RICH_TEXT_RUNS) else {
+        return vec![block];
+    };
+    let rest: Vec<Vec<RichText>> = runs
+        .split_off(MAX_RICH_TEXT_RUNS)
+        .chunks(MAX_RICH_TEXT_RUNS)
+        .map(<[RichText]>::to_vec)
+        .collect();
+
+    if matches!(
+        block.block_type,
+        BlockType::BulletedListItem { .. }
+            | BlockType::NumberedListItem { .. }
+            | BlockType::ToDo { .. }
+            | BlockType::Toggle { .. }
+    ) {
+        if let Some(slot) = block_children_slot(&mut block) {
+            let mut children: Vec<Block> = rest.into_iter().map(paragraph_block).collect();
+            children.extend(slot.take().unwrap_or_default());
+            *slot = Some(children);
+        }
+        return vec![block];
+    }
+
+    // Anything under the block belongs after all of its text.
+    let children = block_children_slot(&mut block).and_then(Option::take);
+    let mut pieces = vec![block.clone()];
+    for runs in rest {
+        let mut piece = Block {
+            id: None,
+            ..block.clone()
+        };
+        if let Some(slot) = block_text_slot(&mut piece) {
+            *slot = runs;
+        }
+        if let BlockType::Code { ref mut code } = piece.block_type {
+            code.caption.clear();
+        }
+        pieces.push(piece);
+    }
+    if let (Some(children), Some(slot)) = (children, pieces.last_mut().and_then(block_children_slot)) {
+        *slot = Some(children);
+    }
+    pieces
+}
+
+fn paragraph_block(rich_text: Vec<RichText>) -> Block {
+    let paragraph = ParagraphValue {
+        rich_text,
+        color: Some(TextColor::Default),
+        children: None,
+    };
+    Block {
+        block_type: BlockType::Paragraph { paragraph },
+        ..Default::default()
+    }
+}
+
+/// The block that shows a link the way `preview` says to.
+fn preview_block(preview: Preview, url: String) -> Block {
+    let external = |url| File::External {
+        external: ExternalFile { url },
+    };
+    let block_type = match preview {
+        Preview::Bookmark => BlockType::Bookmark {
+            bookmark: BookmarkValue {
+                caption: Vec::new(),
+                url,
+            },
+        },
+        Preview::Video => BlockType::Video {
+            video: VideoValue {
+                file_type: external(url),
+            },
+        },
+        Preview::Pdf => BlockType::Pdf {
+            pdf: PdfValue {
+                caption: Vec::new(),
+                file_type: external(url),
+            },
+        },
+        Preview::Embed => BlockType::Embed {
+            embed: EmbedValue { url },
+        },
+    };
+    Block {
+        block_type,
+        ..Default::default()
+    }
+}
+
+/// Text becomes paragraphs, split wherever an image or an HTML paragraph break turns up.
+fn inline_blocks(pieces: Vec<Inline>) -> Vec<Block> {
+    let mut blocks = Vec::new();
+    let mut rich_text: Vec<RichText> = Vec::new();
+    for piece in pieces {
+        let images = match piece {
+            Inline::Text(text) => {
+                rich_text.extend(text);
+                continue;
+            }
+            Inline::Image { blocks, .. } => blocks,
+            Inline::Break => Vec::new(),
+        };
+        if !is_blank(&rich_text) {
+            blocks.push(paragraph_block(std::mem::take(&mut rich_text)));
+        }
+        rich_text.clear();
+        blocks.extend(images);
+    }
+    if !is_blank(&rich_text) {
+        blocks.push(paragraph_block(rich_text));
+    }
+    blocks
+}
+
+fn is_blank_token(token: &Token) -> bool {
+    match token {
+        Token::Text(text) => text.trim().is_empty(),
+        Token::Comment => true,
+        _ => false,
+    }
+}
+
+/// Swap in superscript or subscript characters, if the text has them all.
+/// True if these runs have nothing worth a paragraph of its own.
+fn is_blank(rich_text: &[RichText]) -> bool {
+    rich_text.iter().all(|xs| match xs {
+        RichText::Text { text, .. } => text.content.trim().is_empty(),
+        _ => false,
+    })
+}
+
+/// What the API calls this kind of block: `paragraph`, `code`, and so on.
+fn block_kind(block: &Block) -> String {
+    serde_json::to_value(block)
+        .ok()
+        .and_then(|xs| xs.get("type").and_then(|kind| kind.as_str()).map(str::to_owned))
+        .unwrap_or_else(|| "block".to_string())
+}
+
+/// A block and all of its descendants.
+fn count_blocks(block: &Block) -> usize {
+    1 + block_children(block).map_or(0, |xs| xs.iter().map(count_blocks).sum())
+}
+
+/// How many bytes this block and everything under it add to a request, give or take
+/// a comma.
+fn payload_size(block: &Block) -> usize {
+    serde_json::to_vec(block).map_or(0, |xs| xs.len()) + 1
+}
+
+/// GitHub's rules for heading anchors: lowercase, drop punctuation, spaces become hyphens.
+/// Repeated headings get `-1`, `-2`, and so on, just like on GitHub.
+fn heading_slug(text: &str, existing: &VecDeque<String>) -> String {
+    let base: String = text
+        .trim()
+        .to_lowercase()
+        .chars()
+        .filter_map(|c| match c {
+            ' ' => Some('-'),
+            '-' | '_' => Some(c),
+            c if c.is_alphanumeric() => Some(c),
+            _ => None,
+        })
+        .collect();
+    let mut slug = base.clone();
+    let mut count = 0;
+    while existing.contains(&slug) {
+        count += 1;
+        slug = format!("{base}-{count}");
+    }
+    slug
+}
+
+/// The icon on every footnote's callout.
+const FOOTNOTE_EMOJI: &str = "🗒️";
+
+/// The fragment we use to find a footnote definition.
+fn footnote_fragment(identifier: &str) -> String {
+    format!("fn-{identifier}")
+}
+
+/// If this text run is an in-document link still waiting to be resolved, the fragment
+/// it points to. These runs have no link yet, just the `#fragment` in `href`.
+fn internal_fragment(run: &RichText) -> Option<&str> {
+    match run {
+        RichText::Text {
+            text, href: Some(href), ..
+        } if text.link.is_none() => href.strip_prefix('#'),
+        _ => None,
+    }
+}
+
+/// The block's own rich text, not counting anything in its children.
+fn block_rich_text_mut(block: &mut Block) -> Vec<&mut RichText> {
+    if let BlockType::TableRow { ref mut table_row } = block.block_type {
+        return table_row.cells.iter_mut().flatten().collect();
+    }
+    block_text_slot(block).map_or_else(Vec::new, |xs| xs.iter_mut().collect())
+}
+
+/// The rich text of the block types we generate that have their own text.
+fn block_text_slot(block: &mut Block) -> Option<&mut Vec<RichText>> {
+    let rich_text = match block.block_type {
+        BlockType::BulletedListItem {
+            ref mut bulleted_list_item,
+        } => &mut bulleted_list_item.rich_text,
+        BlockType::NumberedListItem {
+            ref mut numbered_list_item,
+        } => &mut numbered_list_item.rich_text,
+        BlockType::Paragraph { ref mut paragraph } => &mut paragraph.rich_text,
+        BlockType::Quote { ref mut quote } => &mut quote.rich_text,
+        BlockType::ToDo { ref mut to_do } => &mut to_do.rich_text,
+        BlockType::Callout { ref mut callout } => &mut callout.rich_text,
+        BlockType::Toggle { ref mut toggle } => &mut toggle.rich_text,
+        BlockType::Heading1 { ref mut heading_1 } => &mut heading_1.rich_text,
+        BlockType::Heading2 { ref mut heading_2 } => &mut heading_2.rich_text,
+        BlockType::Heading3 { ref mut heading_3 } => &mut heading_3.rich_text,
+        BlockType::Code { ref mut code } => &mut code.rich_text,
+        _ => return None,
+    };
+    Some(rich_text)
+}
+
+fn block_children_mut(block: &mut Block) -> Option<&mut Vec<Block>> {
+    block_children_slot(block)?.as_mut()
+}
+
+/// Where a block keeps its children, whether or not it has any yet.
+fn block_children_slot(block: &mut Block) -> Option<&mut Option<Vec<Block>>> {
+    let slot = match block.block_type {
+        BlockType::BulletedListItem {
+            ref mut bulleted_list_item,
+        } => &mut bulleted_list_item.children,
+        BlockType::NumberedListItem {
+            ref mut numbered_list_item,
+        } => &mut numbered_list_item.children,
+        BlockType::Paragraph { ref mut paragraph } => &mut paragraph.children,
+        BlockType::Quote { ref mut quote } => &mut quote.children,
+        BlockType::ToDo { ref mut to_do } => &mut to_do.children,
+        BlockType::Toggle { ref mut toggle } => &mut toggle.children,
+        BlockType::Table { ref mut table } => &mut table.children,
+        _ => return None,
+    };
+    Some(slot)
 }
 
 fn split_block_from_children(block: Block) -> (Block, Option<VecDeque<Block>>) {
+    // Notion refuses to create a table with no rows, so the first batch of rows
+    // stays with the table and the remainder get appended to it afterwards.
+    // That batch is as many rows as fit in one request, by count and by size.
+    if let BlockType::Table { ref table } = block.block_type {
+        let mut table = table.clone();
+        let mut rows = table.children.take().unwrap_or_default();
+        let mut bytes = payload_size(&block).saturating_sub(rows.iter().map(payload_size).sum());
+        let fits = rows
+            .iter()
+            .take(MAX_CHILDREN)
+            .take_while(|row| {
+                bytes += payload_size(row);
+                bytes <= MAX_PAYLOAD_BYTES
+            })
+            .count()
+            .max(1);
+        let rest = if rows.len() > fits {
+            Some(VecDeque::from(rows.split_off(fits)))
+        } else {
+            None
+        };
+        table.children = Some(rows);
+        let mut replacement = block.clone();
+        replacement.block_type = BlockType::Table { table };
+        return (replacement, rest);
+    }
+
     // There are many block types here that we skip because we are never
     // generating them while converting from markdown. We also skip block
     // types that do not have a `children` field.
@@ -823,6 +2483,8 @@ fn split_block_from_children(block: Block) -> (Block, Option<VecDeque<Block>>) {
         BlockType::NumberedListItem { ref numbered_list_item } => &numbered_list_item.children,
         BlockType::Paragraph { ref paragraph } => &paragraph.children,
         BlockType::Quote { ref quote } => &quote.children,
+        BlockType::ToDo { ref to_do } => &to_do.children,
+        BlockType::Toggle { ref toggle } => &toggle.children,
         _ => &None,
     };
     let Some(children) = maybe_kids else {
@@ -854,6 +2516,16 @@ fn split_block_from_children(block: Block) -> (Block, Option<VecDeque<Block>>) {
             quote.children = None;
             replacement.block_type = BlockType::Quote { quote };
         }
+        BlockType::ToDo { ref to_do } => {
+            let mut to_do = to_do.clone();
+            to_do.children = None;
+            replacement.block_type = BlockType::ToDo { to_do };
+        }
+        BlockType::Toggle { ref toggle } => {
+            let mut toggle = toggle.clone();
+            toggle.children = None;
+            replacement.block_type = BlockType::Toggle { toggle };
+        }
         _ => {}
     }
     (replacement, Some(VecDeque::from(children.clone())))
@@ -870,51 +2542,622 @@ mod libtest {
         let blocks = convert(input);
         blocks.iter().for_each(|xs| {
             crate::tests::debug_print(xs);
-            if PageMaker::block_has_deep_children(0, xs) {
+            if block_has_deep_children(0, xs) {
                 eprintln!("    ^^^^ too deep!");
             }
         });
         // assert_eq!(true, false);
     }
 
-    /// This creates a page. Be sure you want this.
+    #[test]
+    fn deep_task_lists_are_split() {
+        let input = include_str!("../fixtures/task_list.md");
+        let blocks = convert(input);
+        assert!(block_has_deep_children(0, &blocks[1]));
+        let (head, children) = split_block_from_children(blocks[1].clone());
+        match head.block_type {
+            BlockType::ToDo { to_do } => {
+                assert_eq!(to_do.checked, Some(true));
+                assert!(to_do.children.is_none());
+            }
+            _ => panic!("expected a to-do block"),
+        }
+        assert_eq!(children.map(|xs| xs.len()), Some(2));
+    }
+
+    #[test]
+    fn deep_quotes_are_split() {
+        let input = include_str!("../fixtures/block_quotes.md");
+        let blocks = convert(input);
+        assert!(!block_has_deep_children(0, &blocks[0]));
+        assert!(block_has_deep_children(0, &blocks[1]));
+        let (head, children) = split_block_from_children(blocks[1].clone());
+        match head.block_type {
+            BlockType::Quote { quote } => assert!(quote.children.is_none()),
+            _ => panic!("expected a quote"),
+        }
+        assert_eq!(children.map(|xs| xs.len()), Some(3));
+    }
+
+    #[test]
+    fn callout_bodies_are_deferred() {
+        let input = "- a list item\n\n  > [!CAUTION]\n  > This is hot.\n  >\n  > 1. Let it cool.\n";
+        let Conversion { blocks, deferred, .. } = convert_for_upload(input, &ConvertOptions::default());
+        assert_eq!(deferred.len(), 1);
+        let client = FakeNotion::default();
+        let mut maker = PageMaker::new(&client, "parent", BTreeMap::new());
+        maker.deferred = deferred;
+        assert!(!maker.block_has_deferred(&blocks[0]));
+        assert!(block_has_local_ids(&blocks[0]));
+
+        let (_item, children) = maker.split_block(blocks[0].clone());
+        let children = children.expect("the list item should have children");
+        let callout = children[0].clone();
+        assert!(callout.id.as_ref().is_some_and(|id| id.starts_with(LOCAL_ID_PREFIX)));
+        assert!(maker.block_has_deferred(&callout));
+        let (callout, body) = maker.split_block(callout);
+        assert!(matches!(callout.block_type, BlockType::Callout { .. }));
+        assert_eq!(body.map(|xs| xs.len()), Some(1));
+        assert!(maker.deferred.is_empty());
+    }
+
+    #[test]
+    fn footnote_references_are_linked() {
+        let input = include_str!("../fixtures/footnotes.md");
+        let conversion = convert_for_upload(input, &ConvertOptions::default());
+        // The paragraph and the first list item refer to footnotes that exist.
+        assert_eq!(conversion.pending.len(), 2);
+        assert_eq!(conversion.anchors.len(), 2);
+        assert!(conversion.blocks.iter().any(block_has_local_ids));
+
+        let client = FakeNotion::default();
+        let mut maker = PageMaker::new(&client, "parent", BTreeMap::new());
+        maker.anchors = conversion.anchors.clone();
+        // Pretend we've created everything.
+        conversion.anchors.values().enumerate().for_each(|(idx, local)| {
+            maker.created.insert(local.clone(), format!("0000-000{idx}"));
+        });
+        let local = conversion.blocks[0]
+            .id
+            .clone()
+            .expect("the first paragraph should have a local id");
+        let mut paragraph = conversion.pending[&local].clone();
+        maker.resolve_internal_links("abcd-ef", &mut paragraph);
+        let links: Vec<String> = match paragraph.block_type {
+            BlockType::Paragraph { paragraph } => paragraph
+                .rich_text
+                .iter()
+                .filter_map(|xs| match xs {
+                    RichText::Text { text, .. } => text.link.as_ref().map(|link| link.url.clone()),
+                    _ => None,
+                })
+                .collect(),
+            _ => panic!("expected a paragraph"),
+        };
+        assert_eq!(links.len(), 2);
+        assert!(links
+            .iter()
+            .all(|xs| xs.starts_with("https://www.notion.so/abcdef#0000000")));
+    }
+
+    #[test]
+    fn fragment_links_point_at_headings() {
+        let input = include_str!("../fixtures/fragment_links.md");
+        let conversion = convert_for_upload(input, &ConvertOptions::default());
+        let unresolved: Vec<String> = conversion.diagnostics.iter().map(|xs| xs.to_string()).collect();
+        assert_eq!(
+            unresolved,
+            vec!["4:13: link points at missing heading #troubleshooting"]
+        );
+        let mut fragments: Vec<&String> = conversion.anchors.keys().collect();
+        fragments.sort();
+        assert_eq!(fragments, vec!["deploy-steps", "setup", "setup-1"]);
+        // Only the headings that are linked to need ids.
+        assert!(conversion.blocks[0].id.is_none());
+        assert!(conversion.blocks[2].id.is_some());
+        assert_eq!(conversion.pending.len(), 2);
+
+        let client = FakeNotion::default();
+        let mut maker = PageMaker::new(&client, "parent", BTreeMap::new());
+        maker.anchors = conversion.anchors.clone();
+        // Pretend only the first setup heading was created.
+        maker
+            .created
+            .insert(conversion.anchors["setup"].clone(), "1111-2222".to_string());
+        let local = conversion.blocks[1]
+            .id
+            .clone()
+            .expect("the linking paragraph should have a local id");
+        let mut paragraph = conversion.pending[&local].clone();
+        maker.resolve_internal_links("abcd-ef", &mut paragraph);
+        let targets: Vec<(Option<String>, Option<String>)> = match paragraph.block_type {
+            BlockType::Paragraph { paragraph } => paragraph
+                .rich_text
+                .iter()
+                .filter_map(|xs| match xs {
+                    RichText::Text { text, href, .. } => {
+                        Some((text.link.as_ref().map(|link| link.url.clone()), href.clone()))
+                    }
+                    _ => None,
+                })
+                .filter(|(link, href)| link.is_some() || href.is_some())
+                .collect(),
+            _ => panic!("expected a paragraph"),
+        };
+        let url = "https://www.notion.so/abcdef#11112222".to_string();
+        assert_eq!(targets, vec![(Some(url.clone()), Some(url))]);
+    }
+
+    #[test]
+    fn toggle_heading_contents_are_deferred() {
+        let options = ConvertOptions {
+            headings: HeadingPolicy::Toggle,
+            ..Default::default()
+        };
+        let conversion = convert_for_upload(include_str!("../fixtures/deep_headings.md"), &options);
+        // Two level 4 toggles at the top, and a level 5 toggle inside the first one.
+        assert_eq!(conversion.blocks.len(), 6);
+        assert_eq!(conversion.deferred.len(), 3);
+        let first = conversion.blocks[2]
+            .id
+            .clone()
+            .expect("the toggle should have a local id");
+        assert_eq!(conversion.deferred[&first].len(), 2);
+    }
+
+    #[test]
+    fn long_tables_are_split() {
+        let mut input = String::from("| n | square |\n|---|---|\n");
+        (0..250).for_each(|n| input.push_str(format!("| {n} | {} |\n", n * n).as_str()));
+        let blocks = convert(input.as_str());
+        assert_eq!(blocks.len(), 1);
+        assert!(block_has_too_many_children(&blocks[0]));
+        let (head, rest) = split_block_from_children(blocks[0].clone());
+        match head.block_type {
+            BlockType::Table { table } => {
+                assert_eq!(table.children.map(|xs| xs.len()), Some(MAX_CHILDREN));
+            }
+            _ => panic!("expected a table"),
+        }
+        // 250 rows plus the header row
+        assert_eq!(rest.map(|xs| xs.len()), Some(151));
+    }
+
     #[tokio::test]
-    #[ignore]
-    async fn creating_by_chunks() {
-        let _ignored = dotenvy::dotenv().unwrap();
-        let notion_key =
-            std::env::var("NOTION_API_KEY").expect("The test creating_by_chunks needs the env var NOTION_API_KEY.");
-        let notion = Client::new(notion_key, None).expect("should be able to make a client");
-        let parent =
-            std::env::var("PARENT_ID").expect("The test creating_by_chunks needs a parent page id in PARENT_ID");
-
-        let mut properties: BTreeMap<String, PageProperty> = BTreeMap::new();
+    async fn planning_nested_lists() {
+        let input = include_str!("../fixtures/nested_lists.md");
+        let plan = plan_page(input, &ConvertOptions::default())
+            .await
+            .expect("planning should not need Notion");
+        assert_eq!(plan.requests[0], PlannedRequest::CreatePage);
+        assert_eq!(plan.count(|xs| matches!(xs, PlannedRequest::CreatePage)), 1);
+        assert_eq!(plan.count(|xs| matches!(xs, PlannedRequest::Update { .. })), 0);
+        // One append per level that had to wait for its parent: the page, the first
+        // item, and the item three levels down. Splitting every deep block into
+        // requests of its own took seven.
+        assert_eq!(plan.count(|xs| matches!(xs, PlannedRequest::Append { .. })), 3);
+        assert_eq!(plan.splits, 2);
+        // Every block in the page goes up exactly once.
+        let sent: usize = plan
+            .requests
+            .iter()
+            .map(|xs| match xs {
+                PlannedRequest::Append { total, .. } => *total,
+                _ => 0,
+            })
+            .sum();
+        let expected: usize = convert(input).iter().map(count_blocks).sum();
+        assert_eq!(sent, expected);
+    }
+
+    #[tokio::test]
+    async fn planning_link_updates() {
+        let plan = plan_page(
+            include_str!("../fixtures/fragment_links.md"),
+            &ConvertOptions::default(),
+        )
+        .await
+        .expect("planning should not need Notion");
+        assert_eq!(plan.count(|xs| matches!(xs, PlannedRequest::Update { .. })), 2);
+        assert!(plan
+            .to_string()
+            .ends_with("1 create, 1 appends, 2 updates; 0 nesting splits"));
+    }
+
+    /// Blocks as JSON, without anything Notion adds to them or we add for our own use.
+    fn content_only(blocks: &[Block]) -> serde_json::Value {
+        fn strip(value: &mut serde_json::Value) {
+            match value {
+                serde_json::Value::Object(map) => {
+                    ["id", "parent", "object", "has_children"].iter().for_each(|key| {
+                        map.remove(*key);
+                    });
+                    map.values_mut().for_each(strip);
+                }
+                serde_json::Value::Array(items) => items.iter_mut().for_each(strip),
+                _ => {}
+            }
+        }
+        let mut value = serde_json::to_value(blocks).expect("blocks always serialize");
+        strip(&mut value);
+        value
+    }
+
+    fn title(content: &str) -> BTreeMap<String, PageProperty> {
         let text = Text {
-            content: "Nested list test".to_string(),
+            content: content.to_string(),
             link: None,
         };
         let title = vec![RichText::Text {
             text,
             annotations: None,
-            plain_text: Some("Nested list test".to_string()),
+            plain_text: Some(content.to_string()),
             href: None,
         }];
-        properties.insert("title".to_string(), PageProperty::Title { id: None, title });
+        BTreeMap::from([("title".to_string(), PageProperty::Title { id: None, title })])
+    }
 
+    #[tokio::test(start_paused = true)]
+    async fn creating_by_chunks() {
+        let notion = FakeNotion::default();
+        let parent = "parent-page";
         let input = include_str!("../fixtures/nested_lists.md");
-        let page = create_page(&notion, input, parent.as_str(), properties)
+        let page = create_page(&notion, input, parent, title("Nested list test"))
             .await
             .expect("create_page() should succeed in testing");
         assert!(!page.id.is_empty());
-        assert!(matches!(page.parent, Parent::PageId { .. }));
         match page.parent {
-            Parent::None => {}
-            Parent::PageId { page_id } => {
-                assert_eq!(page_id, parent);
-            }
-            Parent::BlockId { .. } => {}
-            Parent::Workspace { .. } => {}
-            Parent::DatabaseId { .. } => {}
+            Parent::PageId { page_id } => assert_eq!(page_id, parent),
+            _ => panic!("expected a page parent"),
+        }
+        assert_eq!(
+            content_only(&notion.children(page.id.as_str())),
+            content_only(&convert(input))
+        );
+
+        // The plan is exactly what got sent.
+        let plan = plan_page(input, &ConvertOptions::default())
+            .await
+            .expect("planning should work");
+        let kinds = |requests: &[PlannedRequest]| -> Vec<(usize, usize)> {
+            requests
+                .iter()
+                .map(|xs| match xs {
+                    PlannedRequest::Append { blocks, total, .. } => (*blocks, *total),
+                    _ => (0, 0),
+                })
+                .collect()
+        };
+        assert_eq!(kinds(&notion.requests()), kinds(&plan.requests));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn packing_respects_limits() {
+        let mut input = String::from("- long\n");
+        (0..150).for_each(|idx| input.push_str(format!("  - item {idx}\n").as_str()));
+        input.push_str("- deep\n  - a\n    - b\n      - c\n        - d\n- shallow\n  - e\n\n| a |\n| - |\n");
+        (0..120).for_each(|idx| input.push_str(format!("| {idx} |\n").as_str()));
+
+        let notion = FakeNotion::default();
+        let page = create_page(&notion, input.as_str(), "parent-page", title("Packing"))
+            .await
+            .expect("the fake enforces Notion's limits, and packing should stay inside them");
+        assert_eq!(
+            content_only(&notion.children(page.id.as_str())),
+            content_only(&convert(input.as_str()))
+        );
+        let appends: Vec<(usize, usize)> = notion
+            .requests()
+            .iter()
+            .filter_map(|xs| match xs {
+                PlannedRequest::Append { blocks, total, .. } => Some((*blocks, *total)),
+                _ => None,
+            })
+            .collect();
+        // The page's four blocks, with the shallow list whole and the table's first 100
+        // rows; the long list in two; `a` on its own, because `d` is too deep for it;
+        // everything under `a`; the rest of the table.
+        assert_eq!(appends, vec![(4, 105), (100, 100), (50, 50), (1, 1), (1, 3), (21, 21)]);
+    }
+
+    #[test]
+    fn long_rich_text_is_split() {
+        let runs = |block: &Block| block_rich_text_mut(&mut block.clone()).len();
+        // 125 runs of 2000 characters.
+        let long = "x".repeat(250_000);
+
+        let blocks = convert(format!("{long}\n\n> {long}\n").as_str());
+        assert_eq!(blocks.iter().map(runs).collect::<Vec<_>>(), vec![100, 25, 100, 25]);
+        assert!(matches!(blocks[1].block_type, BlockType::Paragraph { .. }));
+        assert!(matches!(blocks[3].block_type, BlockType::Quote { .. }));
+
+        let blocks = convert(format!("- {long}\n  - nested\n").as_str());
+        assert_eq!(blocks.len(), 1);
+        let children = block_children(&blocks[0]).expect("the rest of the item goes inside it");
+        assert_eq!(children.iter().map(runs).collect::<Vec<_>>(), vec![25, 1]);
+        assert!(matches!(children[0].block_type, BlockType::Paragraph { .. }));
+        assert!(matches!(children[1].block_type, BlockType::BulletedListItem { .. }));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn requests_stay_under_payload_limits() {
+        let appends = |notion: &FakeNotion| -> Vec<(usize, usize)> {
+            notion
+                .requests()
+                .iter()
+                .filter_map(|xs| match xs {
+                    PlannedRequest::Append { blocks, total, .. } => Some((*blocks, *total)),
+                    _ => None,
+                })
+                .collect()
+        };
+
+        // 30 items of 41 blocks each: 24 of them make 984 blocks, and 25 would be too many.
+        let mut input = String::new();
+        for item in 0..30 {
+            input.push_str(format!("- item {item}\n").as_str());
+            (0..40).for_each(|sub| input.push_str(format!("  - sub {sub}\n").as_str()));
+        }
+        let notion = FakeNotion::default();
+        let page = create_page(&notion, input.as_str(), "parent-page", title("Blocks"))
+            .await
+            .expect("requests should stay under 1000 blocks");
+        assert_eq!(appends(&notion), vec![(24, 984), (6, 246)]);
+        assert_eq!(
+            content_only(&notion.children(page.id.as_str())),
+            content_only(&convert(input.as_str()))
+        );
+
+        // Paragraphs of ten long runs, and a table with long cells: megabytes in all.
+        let paragraph = "y".repeat(20_000);
+        let mut input = format!("{paragraph}\n\n").repeat(60);
+        input.push_str("| a | b | c |\n| - | - | - |\n");
+        let cell = "z".repeat(1900);
+        (0..100).for_each(|_| input.push_str(format!("| {cell} | {cell} | {cell} |\n").as_str()));
+        let notion = FakeNotion::default();
+        let page = create_page(&notion, input.as_str(), "parent-page", title("Bytes"))
+            .await
+            .expect("requests should stay under 500KB");
+        assert!(appends(&notion).len() > 4);
+        assert_eq!(
+            content_only(&notion.children(page.id.as_str())),
+            content_only(&convert(input.as_str()))
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn every_fixture_uploads() {
+        for entry in std::fs::read_dir("fixtures").expect("the fixtures directory is readable") {
+            let path = entry.expect("fixtures are listable").path();
+            let input = std::fs::read_to_string(&path).expect("fixtures are readable");
+            let notion = FakeNotion::default();
+            let page = create_page(&notion, input.as_str(), "parent-page", title("fixture"))
+                .await
+                .unwrap_or_else(|e| panic!("{} should upload: {e:?}", path.display()));
+            let uploaded = notion.children(page.id.as_str());
+            assert_eq!(
+                uploaded.iter().map(count_blocks).sum::<usize>(),
+                convert(input.as_str()).iter().map(count_blocks).sum::<usize>(),
+                "{} lost blocks on the way up",
+                path.display()
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn front_matter_keeps_only_the_title_under_a_page() {
+        let notion = FakeNotion::default();
+        let input = include_str!("../fixtures/front_matter.md");
+        let (page, diagnostics) = create_page_with_report(
+            &notion,
+            input,
+            "parent-page",
+            BTreeMap::new(),
+            &ConvertOptions::default(),
+        )
+        .await;
+        let page = page.expect("the extra fields should be left behind, not sent");
+        assert!(matches!(page.properties.get("title"), Some(PageProperty::Title { .. })));
+        assert_eq!(page.properties.len(), 1);
+        let dropped: Vec<String> = diagnostics.iter().map(|xs| xs.to_string()).collect();
+        assert_eq!(dropped.len(), 5, "{dropped:?}");
+        assert!(
+            dropped.iter().all(|xs| xs.starts_with("1:1: yaml truncated")),
+            "{dropped:?}"
+        );
+        assert!(dropped.iter().any(|xs| xs.contains("`tags`")), "{dropped:?}");
+    }
+
+    #[tokio::test]
+    async fn front_matter_becomes_properties_in_a_database() {
+        let notion = FakeNotion::default();
+        let input = include_str!("../fixtures/front_matter.md");
+        let options = ConvertOptions {
+            parent: ParentKind::Database,
+            ..Default::default()
+        };
+        let (page, diagnostics) =
+            create_page_with_report(&notion, input, "a-database", BTreeMap::new(), &options).await;
+        let page = page.expect("a database takes every field");
+        assert!(diagnostics.is_empty(), "{diagnostics:?}");
+        assert!(matches!(page.parent, Parent::DatabaseId { ref database_id } if database_id == "a-database"));
+        assert_eq!(page.properties.len(), 6);
+        assert!(matches!(
+            page.properties.get("tags"),
+            Some(PageProperty::MultiSelect { .. })
+        ));
+        assert!(matches!(
+            page.properties.get("published"),
+            Some(PageProperty::Checkbox { checkbox: true, .. })
+        ));
+    }
+
+    #[test]
+    fn unparseable_front_matter_is_reported() {
+        let (blocks, diagnostics) =
+            convert_with_report("---\ntitle: [unclosed\n---\n\nBody.\n", &ConvertOptions::default());
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(diagnostics.len(), 1);
+        assert!(matches!(diagnostics[0].outcome, Outcome::Unparsed(_)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn long_code_and_cells_fit() {
+        let code = "let x = 1\n".repeat(25_000);
+        let input = format!("```rust title=\"big.rs\"\n{code}```\n");
+        let notion = FakeNotion::default();
+        let (page, diagnostics) = create_page_with_report(
+            &notion,
+            input.as_str(),
+            "parent-page",
+            title("Code"),
+            &ConvertOptions::default(),
+        )
+        .await;
+        let page = page.expect("a 250KB fence should upload");
+        assert!(diagnostics.is_empty(), "{diagnostics:?}");
+        let blocks = notion.children(page.id.as_str());
+        let pieces: Vec<&CodeValue> = blocks
+            .iter()
+            .filter_map(|xs| match &xs.block_type {
+                BlockType::Code { code } => Some(code),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            pieces.iter().map(|xs| xs.rich_text.len()).collect::<Vec<_>>(),
+            vec![100, 25]
+        );
+        assert!(!pieces[0].caption.is_empty());
+        assert!(pieces[1].caption.is_empty());
+        let uploaded: String = pieces
+            .iter()
+            .flat_map(|xs| xs.rich_text.iter())
+            .filter_map(|xs| match xs {
+                RichText::Text { text, .. } => Some(text.content.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(uploaded, code.trim_end());
+
+        let cell = "**a** b ".repeat(60);
+        let input = format!("| x |\n| - |\n| {cell} |\n");
+        let notion = FakeNotion::default();
+        let (page, diagnostics) = create_page_with_report(
+            &notion,
+            input.as_str(),
+            "parent-page",
+            title("Cells"),
+            &ConvertOptions::default(),
+        )
+        .await;
+        assert!(page.is_ok(), "{page:?}");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].node, "tableCell");
+
+        // Quotes take two bytes each in JSON, so this is too big however it's split.
+        let input = format!("Before.\n\n```\n{}\n```\n", "\"".repeat(200_000));
+        let notion = FakeNotion::default();
+        let (page, diagnostics) = create_page_with_report(
+            &notion,
+            input.as_str(),
+            "parent-page",
+            title("Huge"),
+            &ConvertOptions::default(),
+        )
+        .await;
+        let page = page.expect("the block that's too big is left out");
+        assert_eq!(notion.children(page.id.as_str()).len(), 1);
+        assert_eq!(diagnostics.len(), 1);
+        assert!(
+            diagnostics[0].to_string().contains("a code block of"),
+            "{}",
+            diagnostics[0]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn links_are_patched_after_upload() {
+        let notion = FakeNotion::default();
+        let input = include_str!("../fixtures/footnotes.md");
+        let page = create_page(&notion, input, "parent-page", title("Footnotes"))
+            .await
+            .expect("create_page() should succeed in testing");
+        let updates = notion
+            .requests()
+            .iter()
+            .filter(|xs| matches!(xs, PlannedRequest::Update { .. }))
+            .count();
+        assert_eq!(updates, 2);
+        let blocks = notion.children(page.id.as_str());
+        let page_url = format!("https://www.notion.so/{}#", page.id.replace('-', ""));
+        let links: Vec<String> = block_rich_text_mut(&mut blocks[0].clone())
+            .into_iter()
+            .filter_map(|xs| match xs {
+                RichText::Text { text, .. } => text.link.as_ref().map(|link| link.url.clone()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(links.len(), 2);
+        assert!(links.iter().all(|xs| xs.starts_with(page_url.as_str())));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn conflicts_are_retried() {
+        let notion = FakeNotion::default();
+        notion.fail_next(409);
+        notion.fail_next(409);
+        let page = create_page(&notion, "Just a paragraph.\n", "parent-page", title("Retries"))
+            .await
+            .expect("two conflicts should be retried");
+        assert_eq!(notion.children(page.id.as_str()).len(), 1);
+        assert_eq!(notion.requests().len(), 4);
+
+        let notion = FakeNotion::default();
+        notion.fail_next(400);
+        assert!(
+            create_page(&notion, "Just a paragraph.\n", "parent-page", title("Nope"))
+                .await
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn links_without_a_target_block_are_reported() {
+        let notion = FakeNotion::default();
+        let mut maker = PageMaker::new(&notion, "parent-page", BTreeMap::new());
+        maker.anchors.insert("gone".to_string(), format!("{LOCAL_ID_PREFIX}1"));
+        let mut block = convert("A paragraph.\n").remove(0);
+        if let Some(RichText::Text { href, .. }) = block_rich_text_mut(&mut block).into_iter().next() {
+            *href = Some("#gone".to_string());
         }
+        maker.resolve_internal_links("page", &mut block);
+        assert_eq!(maker.diagnostics.len(), 1);
+        assert!(
+            maker.diagnostics[0].to_string().contains("#gone"),
+            "{}",
+            maker.diagnostics[0]
+        );
+        assert!(block_rich_text_mut(&mut block)
+            .iter()
+            .all(|xs| internal_fragment(xs).is_none()));
+    }
+
+    #[tokio::test]
+    async fn failed_uploads_still_report_losses() {
+        let notion = FakeNotion::default();
+        notion.fail_next(400);
+        let input = "<aside>not for Notion</aside>\n\nA paragraph.\n";
+        let (page, diagnostics) = create_page_with_report(
+            &notion,
+            input,
+            "parent-page",
+            title("Lossy"),
+            &ConvertOptions::default(),
+        )
+        .await;
+        assert!(page.is_err());
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].node, "html");
     }
 }
@@ -0,0 +1,160 @@
+//! A token bucket that every Notion request waits on, so that pages migrating at the
+//! same time share Notion's rate limit instead of each running into it on their own.
+
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+/// Notion allows an integration an average of about three requests a second.
+pub const NOTION_REQUESTS_PER_SECOND: f64 = 3.0;
+
+/// The slowest we'll go after rate limiting, as a fraction of the configured rate.
+const SLOWEST: f64 = 0.125;
+
+/// Clones share their bucket, so hand a clone to anything that talks to Notion.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    bucket: Arc<Mutex<Bucket>>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    /// The rate we were asked for, in requests per second.
+    ceiling: f64,
+    /// The rate we're going at, which drops when Notion tells us to slow down.
+    rate: f64,
+    /// A second's worth of requests can go out at once.
+    tokens: f64,
+    updated: Instant,
+    /// Nobody goes before this, because Notion said so.
+    paused_until: Option<Instant>,
+}
+
+impl Bucket {
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.ceiling.max(1.0));
+        self.updated = now;
+    }
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::new(NOTION_REQUESTS_PER_SECOND)
+    }
+}
+
+impl RateLimiter {
+    pub fn new(per_second: f64) -> Self {
+        let ceiling = if per_second.is_finite() && per_second > 0.0 {
+            per_second
+        } else {
+            NOTION_REQUESTS_PER_SECOND
+        };
+        let bucket = Bucket {
+            ceiling,
+            rate: ceiling,
+            tokens: ceiling.max(1.0),
+            updated: Instant::now(),
+            paused_until: None,
+        };
+        Self {
+            bucket: Arc::new(Mutex::new(bucket)),
+        }
+    }
+
+    /// The rate we're going at now, in requests per second.
+    pub fn per_second(&self) -> f64 {
+        self.bucket().rate
+    }
+
+    /// Wait until a request may go out.
+    pub async fn acquire(&self) {
+        loop {
+            let wait = {
+                let mut bucket = self.bucket();
+                let now = Instant::now();
+                bucket.refill(now);
+                match bucket.paused_until {
+                    Some(until) if until > now => until - now,
+                    _ if bucket.tokens >= 1.0 => {
+                        bucket.tokens -= 1.0;
+                        return;
+                    }
+                    _ => Duration::from_secs_f64((1.0 - bucket.tokens) / bucket.rate),
+                }
+            };
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Notion rate-limited a request. Halve the rate, and if Notion said how long to
+    /// wait, hold every request until then.
+    pub fn throttled(&self, retry_after: Option<Duration>) {
+        let mut bucket = self.bucket();
+        let now = Instant::now();
+        bucket.refill(now);
+        bucket.rate = (bucket.rate / 2.0).max(bucket.ceiling * SLOWEST);
+        bucket.tokens = bucket.tokens.min(0.0);
+        if let Some(wait) = retry_after {
+            let until = now + wait;
+            bucket.paused_until = Some(bucket.paused_until.map_or(until, |current| current.max(until)));
+        }
+    }
+
+    /// A request went through. Creep back up towards the configured rate.
+    pub fn succeeded(&self) {
+        let mut bucket = self.bucket();
+        bucket.rate = (bucket.rate + bucket.ceiling / 20.0).min(bucket.ceiling);
+    }
+
+    fn bucket(&self) -> MutexGuard<'_, Bucket> {
+        // Nothing in the bucket can be left half-updated by a panic.
+        self.bucket.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn spaces_out_requests_across_tasks() {
+        let limiter = RateLimiter::new(3.0);
+        let started = Instant::now();
+        let tasks: Vec<_> = (0..9)
+            .map(|_| {
+                let limiter = limiter.clone();
+                tokio::spawn(async move { limiter.acquire().await })
+            })
+            .collect();
+        for task in tasks {
+            task.await.expect("acquiring shouldn't panic");
+        }
+        // Three right away, then one every third of a second.
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_secs(2), "{elapsed:?}");
+        assert!(elapsed < Duration::from_millis(2100), "{elapsed:?}");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn slows_down_after_rate_limits() {
+        let limiter = RateLimiter::new(4.0);
+        limiter.throttled(Some(Duration::from_secs(5)));
+        assert_eq!(limiter.per_second(), 2.0);
+
+        let started = Instant::now();
+        limiter.acquire().await;
+        assert!(started.elapsed() >= Duration::from_secs(5));
+
+        for _ in 0..10 {
+            limiter.throttled(None);
+        }
+        assert_eq!(limiter.per_second(), 0.5, "there's a floor");
+        for _ in 0..100 {
+            limiter.succeeded();
+        }
+        assert_eq!(limiter.per_second(), 4.0, "and a ceiling");
+    }
+}
@@ -9,9 +9,10 @@ mod migrator;
 use std::process::exit;
 
 use cache::Cache;
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 use fzf_wrapped::{run_with_output, Fzf};
 use miette::{IntoDiagnostic, Result};
+use nuc2not::{ConvertOptions, FootnoteStyle, HeadingPolicy, HtmlPolicy, NotionHttp, RetryPolicy, StandaloneLinks};
 use nuclino_rs::{Uuid, Workspace};
 use owo_colors::OwoColorize;
 
@@ -21,12 +22,139 @@ pub struct Args {
     /// How many milliseconds to wait between Nuclino requests.
     #[clap(long, short, global = true, default_value = "750")]
     wait: u64,
+    /// What to do with level 4-6 headings, which Notion doesn't have.
+    #[clap(long, global = true, value_enum, default_value = "bold")]
+    deep_headings: DeepHeadings,
+    /// What to do with raw HTML in the Markdown.
+    #[clap(long, global = true, value_enum, default_value = "interpret")]
+    html: Html,
+    /// Leave footnotes where they're defined instead of gathering them at the end.
+    #[clap(long, global = true)]
+    footnotes_in_place: bool,
+    /// Keep URLs that sit on their own line as links, instead of making them bookmarks,
+    /// videos, PDFs, and embeds.
+    #[clap(long, global = true)]
+    plain_links: bool,
+    /// Parse `$…$` and `$$…$$` as math, making Notion equations.
+    #[clap(long, global = true)]
+    math: bool,
+    /// Resolve relative links and images against this URL. Without it, relative links
+    /// lose their URL and keep their text.
+    #[clap(long, global = true)]
+    base_url: Option<String>,
+    /// Resolve relative image paths against this URL instead.
+    #[clap(long, global = true)]
+    image_base: Option<String>,
+    /// Put a table of contents at the top of pages with more than this many headings.
+    #[clap(long, global = true, value_name = "HEADINGS")]
+    auto_toc: Option<usize>,
+    /// How many times to retry a Notion request that failed because of a conflict, a
+    /// rate limit, a server error, or a lost connection.
+    #[clap(long, global = true, default_value = "5")]
+    retries: u32,
+    /// The most Notion requests to make per second, across every page being migrated.
+    /// This drops on its own for a while whenever Notion says we're going too fast.
+    #[clap(long, global = true, default_value = "3", value_name = "PER_SECOND")]
+    notion_rate: f64,
     #[clap(subcommand)]
     cmd: Command,
 }
 
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum DeepHeadings {
+    /// Make them bold paragraphs.
+    Bold,
+    /// Make them toggle headings that hold the content under them.
+    Toggle,
+    /// Move every heading up a level if the page has no level 1 heading.
+    Shift,
+}
+
+impl From<DeepHeadings> for HeadingPolicy {
+    fn from(value: DeepHeadings) -> Self {
+        match value {
+            DeepHeadings::Bold => HeadingPolicy::BoldParagraph,
+            DeepHeadings::Toggle => HeadingPolicy::Toggle,
+            DeepHeadings::Shift => HeadingPolicy::ShiftUp,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum Html {
+    /// Turn the tags we understand into Notion blocks and styles.
+    Interpret,
+    /// Show HTML blocks as code blocks.
+    Code,
+    /// Leave HTML out.
+    Drop,
+}
+
+impl From<Html> for HtmlPolicy {
+    fn from(value: Html) -> Self {
+        match value {
+            Html::Interpret => HtmlPolicy::Interpret,
+            Html::Code => HtmlPolicy::CodeBlock,
+            Html::Drop => HtmlPolicy::Drop,
+        }
+    }
+}
+
+impl Args {
+    fn convert_options(&self) -> ConvertOptions {
+        let footnotes = if self.footnotes_in_place {
+            FootnoteStyle::InPlace
+        } else {
+            FootnoteStyle::EndOfPage
+        };
+        let standalone_links = if self.plain_links {
+            StandaloneLinks::Inline
+        } else {
+            StandaloneLinks::Preview
+        };
+        ConvertOptions {
+            math: self.math,
+            html: self.html.into(),
+            headings: self.deep_headings.into(),
+            footnotes,
+            standalone_links,
+            base_url: self.base_url.clone(),
+            image_base: self.image_base.clone(),
+            auto_toc: self.auto_toc,
+            ..Default::default()
+        }
+    }
+
+    fn notion(&self, key: &str) -> Result<NotionHttp> {
+        let retries = RetryPolicy {
+            retries: self.retries,
+            ..Default::default()
+        };
+        let notion = NotionHttp::new(key).into_diagnostic()?;
+        Ok(notion.with_retries(retries).with_rate_limit(self.notion_rate))
+    }
+}
+
 #[derive(Clone, Debug, Subcommand)]
 pub enum Command {
+    #[clap(flatten)]
+    Api(ApiCommand),
+    /// Convert a Markdown file to Notion blocks and print them as JSON. Makes no
+    /// API calls, so you don't need any API keys for this. The bodies of callouts and
+    /// toggle headings are shown inside them, as `children`.
+    Convert {
+        /// Print the requests that creating this page would make, instead of the blocks.
+        #[clap(long)]
+        plan: bool,
+        /// The Markdown file to convert, or `-` to read standard input.
+        file: String,
+    },
+}
+
+// The commands that talk to Nuclino and Notion, and so need API keys. Not a doc
+// comment, because clap would show it as the tool's description.
+#[derive(Clone, Debug, Subcommand)]
+pub enum ApiCommand {
     /// Cache a Nuclino workspace locally. You'll be prompted to select the workspace.
     Cache,
     /// Inspect your local cache, listing pages by id.
@@ -48,6 +176,24 @@ pub enum Command {
     },
 }
 
+/// Read the Markdown, convert it, and print what we got.
+async fn convert(file: &str, plan: bool, options: &ConvertOptions) -> Result<()> {
+    let input = if file == "-" {
+        std::io::read_to_string(std::io::stdin()).into_diagnostic()?
+    } else {
+        std::fs::read_to_string(file).into_diagnostic()?
+    };
+    if plan {
+        println!("{}", nuc2not::plan_page(input.as_str(), options).await?);
+    } else {
+        let (blocks, diagnostics) = nuc2not::convert_to_json(input.as_str(), options);
+        println!("{}", serde_json::to_string_pretty(&blocks).into_diagnostic()?);
+        // Diagnostics go to stderr so the JSON stays clean for snapshots.
+        diagnostics.iter().for_each(|xs| eprintln!("{}: {xs}", file.yellow()));
+    }
+    Ok(())
+}
+
 fn choose_workspace(nuclino_key: &str) -> Result<Workspace> {
     let client = nuclino_rs::Client::create(nuclino_key, None);
     let workspaces = client.workspace_list(None, None).into_diagnostic()?.to_vec();
@@ -76,6 +222,10 @@ fn choose_workspace(nuclino_key: &str) -> Result<Workspace> {
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Args::parse();
+    let cmd = match &args.cmd {
+        Command::Convert { file, plan } => return convert(file.as_str(), *plan, &args.convert_options()).await,
+        Command::Api(cmd) => cmd.clone(),
+    };
 
     let _ignored = dotenvy::dotenv().into_diagnostic()?;
     let notion_key =
@@ -85,24 +235,26 @@ async fn main() -> Result<()> {
 
     let found = choose_workspace(nuclino_key.as_str())?;
     let mut cache = Cache::new(nuclino_key, &args, &found)?;
+    let options = args.convert_options();
+    let notion = args.notion(notion_key.as_str())?;
 
-    match args.cmd {
-        Command::Cache => {
+    match cmd {
+        ApiCommand::Cache => {
             println!("Caching the {} workspace...", found.name().blue());
             let count = cache.cache_workspace()?;
             println!("    {count} items cached");
         }
-        Command::InspectCache => {
+        ApiCommand::InspectCache => {
             cache.print_details()?;
         }
-        Command::MigratePage { pages, parent } => {
+        ApiCommand::MigratePage { pages, parent } => {
             let uuids: Vec<Uuid> = pages.iter().filter_map(|xs| Uuid::try_parse(xs).ok()).collect();
-            let migrator = migrator::Migrator::new(notion_key, parent.clone())?;
+            let migrator = migrator::Migrator::new(notion, parent.clone(), options);
             migrator.migrate_pagelist(cache, uuids.as_slice()).await?;
         }
-        Command::MigrateWorkspace { parent } => {
+        ApiCommand::MigrateWorkspace { parent } => {
             println!("Migrating the {} workspace...", found.name().blue());
-            let migrator = migrator::Migrator::new(notion_key, parent)?;
+            let migrator = migrator::Migrator::new(notion, parent, options);
             migrator.migrate(cache, &found).await?;
         }
     }
@@ -4,13 +4,12 @@ use std::collections::{BTreeMap, HashMap};
 use std::sync::Mutex;
 
 use futures::stream::{self, StreamExt};
-use miette::{miette, IntoDiagnostic, Result};
+use miette::{miette, Result};
 use notion_client::endpoints::pages::create::request::CreateAPageRequest;
-use notion_client::endpoints::Client;
 use notion_client::objects::page::{Page as NotionPage, PageProperty};
 use notion_client::objects::parent::Parent;
 use notion_client::objects::rich_text::{RichText, Text};
-use nuc2not::create_page;
+use nuc2not::{create_page_with_report, ConvertOptions, Diagnostic, NotionApi, NotionHttp};
 use nuclino_rs::{Collection, Item, Page, Uuid, Workspace};
 use once_cell::sync::{Lazy, OnceCell};
 use owo_colors::OwoColorize;
@@ -34,16 +33,20 @@ fn cache() -> &'static Cache {
 }
 
 #[derive(Debug, Clone)]
-pub struct Migrator {
-    notion: Client,
+pub struct Migrator<A: NotionApi = NotionHttp> {
+    notion: A,
     parent: String,
+    options: ConvertOptions,
 }
 
-impl Migrator {
-    pub fn new(key: String, parent: String) -> Result<Self> {
-        let notion = notion_client::endpoints::Client::new(key, None).into_diagnostic()?;
-
-        Ok(Self { notion, parent })
+impl<A: NotionApi> Migrator<A> {
+    /// Every page migrates through `notion`, so they all share its retry policy and rate limit.
+    pub fn new(notion: A, parent: String, options: ConvertOptions) -> Self {
+        Self {
+            notion,
+            parent,
+            options,
+        }
     }
 
     /// We walk workspace children instead of getting a full list of workspace pages
@@ -95,7 +98,15 @@ impl Migrator {
         };
 
         let remapped = self.remap(content);
-        let notion_page = create_page(&self.notion, remapped.as_str(), parent_id, properties).await?;
+        let (created, diagnostics) =
+            create_page_with_report(&self.notion, remapped.as_str(), parent_id, properties, &self.options).await;
+        let notion_page = match created {
+            Ok(page) => page,
+            Err(e) => {
+                print_losses(&diagnostics);
+                return Err(e);
+            }
+        };
         urlmap().insert(item.url().to_string(), notion_page.url.clone());
 
         let meta = item.content_meta();
@@ -110,6 +121,7 @@ impl Migrator {
             item.title().bold().green(),
             notion_page.url.yellow()
         );
+        print_losses(&diagnostics);
         if related_files.is_empty() {
             return Ok(notion_page);
         }
@@ -166,7 +178,7 @@ impl Migrator {
             children: None,
         };
 
-        let notion_page = nuc2not::do_create(&self.notion, &new_page_req, 0).await?;
+        let notion_page = nuc2not::do_create(&self.notion, &new_page_req).await?;
         urlmap().insert(collection.url().to_string(), notion_page.url.clone());
 
         let mut subpages: Vec<NotionPage> = Vec::new();
@@ -185,6 +197,18 @@ impl Migrator {
     }
 }
 
+/// Tell the person running the migration which pages need a look by hand.
+fn print_losses(diagnostics: &[Diagnostic]) {
+    if diagnostics.is_empty() {
+        return;
+    }
+    println!(
+        "        {} things did not convert cleanly; review this page by hand:",
+        diagnostics.len().bold()
+    );
+    diagnostics.iter().for_each(|xs| println!("            * {xs}"));
+}
+
 pub fn properties_from_nuclino(page: &Page) -> BTreeMap<String, PageProperty> {
     let mut properties: BTreeMap<String, PageProperty> = BTreeMap::new();
 
@@ -227,3 +251,61 @@ pub fn simple_rich_text(input: &str) -> RichText {
         href: None,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use nuc2not::FakeNotion;
+
+    use super::*;
+
+    fn item(id: &str, title: &str, content: &str) -> Page {
+        let zero = "00000000-0000-4000-8000-000000000000";
+        let item = serde_json::json!({
+            "object": "item",
+            "id": id,
+            "workspaceId": zero,
+            "url": format!("https://app.nuclino.com/t/b/{id}"),
+            "title": title,
+            "createdAt": "2024-07-30T00:00:00.000Z",
+            "createdUserId": zero,
+            "lastUpdatedAt": "2024-07-30T00:00:00.000Z",
+            "lastUpdatedUserId": zero,
+            "fields": {},
+            "content": content,
+            "contentMeta": { "itemIds": [], "fileIds": [] },
+            "highlight": null,
+        });
+        serde_json::from_value(item).expect("test items should deserialize")
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn links_between_pages_are_remapped() {
+        let notion = FakeNotion::default();
+        let parent = notion
+            .create_page(CreateAPageRequest::default())
+            .await
+            .expect("the fake should make a parent page");
+        let migrator = Migrator::new(notion, parent.id.clone(), ConvertOptions::default());
+
+        let first = item("11111111-0000-4000-8000-000000000000", "First", "Hello.\n");
+        let Page::Item(ref inner) = first else { unreachable!() };
+        let migrated = migrator
+            .migrate_item(inner, parent.id.as_str(), properties_from_nuclino(&first))
+            .await
+            .expect("the first page should migrate");
+        assert_eq!(urlmap().get(inner.url()), Some(&migrated.url));
+
+        let link = format!("See [the first page]({}).\n", inner.url());
+        let second = item("22222222-0000-4000-8000-000000000000", "Second", link.as_str());
+        let Page::Item(ref inner) = second else { unreachable!() };
+        let migrated_second = migrator
+            .migrate_item(inner, parent.id.as_str(), properties_from_nuclino(&second))
+            .await
+            .expect("the second page should migrate");
+
+        let blocks = migrator.notion.children(migrated_second.id.as_str());
+        let markdown = nuc2not::blocks_to_markdown(&blocks);
+        assert!(markdown.contains(migrated.url.as_str()), "{markdown}");
+        assert!(!markdown.contains("nuclino.com"), "{markdown}");
+    }
+}
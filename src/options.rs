@@ -0,0 +1,117 @@
+//! Choices about how Markdown turns into Notion blocks.
+
+use std::sync::Arc;
+
+/// A function that takes a link's URL and returns the URL to use instead.
+pub type LinkRewriter = Arc<dyn Fn(&str) -> String + Send + Sync>;
+
+/// Settings for [`convert_with`](crate::convert_with) and [`create_page_with`](crate::create_page_with).
+/// The defaults are what [`convert`](crate::convert) and [`create_page`](crate::create_page) use.
+#[derive(Clone, Default)]
+pub struct ConvertOptions {
+    /// Parse `$x^2$` and `$$` blocks as math. Off by default, because dollar signs
+    /// turn up in ordinary prose far more often than they do in equations.
+    pub math: bool,
+    /// What to do with raw HTML.
+    pub html: HtmlPolicy,
+    /// What to do with headings deeper than Notion's three levels.
+    pub headings: HeadingPolicy,
+    /// Where footnotes go.
+    pub footnotes: FootnoteStyle,
+    /// What a paragraph that's nothing but a URL turns into.
+    pub standalone_links: StandaloneLinks,
+    /// Relative links and images are resolved against this URL. Notion only takes
+    /// absolute URLs, so without a base, relative links are removed and their text kept.
+    pub base_url: Option<String>,
+    /// Relative image paths are resolved against this URL instead of `base_url`, for
+    /// when images live somewhere else.
+    pub image_base: Option<String>,
+    /// Called with the URL of every link before it goes into a block. In-document
+    /// `#fragment` links don't go through this; they're resolved to blocks instead.
+    pub rewrite_link: Option<LinkRewriter>,
+    /// Put a table of contents at the top of any page with more than this many headings.
+    /// Pages that ask for one with a `[TOC]` line already have it where they asked.
+    pub auto_toc: Option<usize>,
+    /// What the parent id passed to `create_page_with` is. Only pages in a database can
+    /// have properties besides a title, so under a page, other front matter fields are
+    /// left out and reported.
+    pub parent: ParentKind,
+}
+
+impl std::fmt::Debug for ConvertOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ConvertOptions")
+            .field("math", &self.math)
+            .field("html", &self.html)
+            .field("headings", &self.headings)
+            .field("footnotes", &self.footnotes)
+            .field("standalone_links", &self.standalone_links)
+            .field("base_url", &self.base_url)
+            .field("image_base", &self.image_base)
+            .field("rewrite_link", &self.rewrite_link.as_ref().map(|_| "<function>"))
+            .field("auto_toc", &self.auto_toc)
+            .field("parent", &self.parent)
+            .finish()
+    }
+}
+
+/// Notion has three levels of heading; Markdown has six. This decides what happens
+/// to levels 4 through 6.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HeadingPolicy {
+    /// Deep headings become paragraphs in bold.
+    #[default]
+    BoldParagraph,
+    /// Deep headings become toggleable level 3 headings, holding everything up to the
+    /// next heading at the same level or higher.
+    Toggle,
+    /// If the document has no level 1 heading, every heading moves up a level. Anything
+    /// still too deep afterwards becomes a bold paragraph.
+    ShiftUp,
+}
+
+/// Raw HTML in Markdown has no direct Notion equivalent.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HtmlPolicy {
+    /// Turn the tags we understand into Notion structures and styles. Block-level HTML
+    /// we don't understand is shown as a code block.
+    #[default]
+    Interpret,
+    /// Show every block of HTML as a code block, and drop inline tags.
+    CodeBlock,
+    /// Leave HTML out entirely.
+    Drop,
+}
+
+/// Where footnote definitions end up on the page.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FootnoteStyle {
+    /// All together under a "Footnotes" heading at the end of the page.
+    #[default]
+    EndOfPage,
+    /// Wherever they're defined in the Markdown.
+    InPlace,
+}
+
+/// The kind of thing a new page is created in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ParentKind {
+    /// Another page. The new page can have a title and nothing else.
+    #[default]
+    Page,
+    /// A database. Front matter fields become properties of the new page, so they
+    /// should match the database's columns.
+    Database,
+}
+
+/// A URL on a line of its own, like `https://youtu.be/…` or `<https://example.com>`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StandaloneLinks {
+    /// A block that shows the link the way Nuclino did: a video player for YouTube and
+    /// Vimeo, a PDF viewer for PDFs, an embed for sites Notion can embed, and a bookmark
+    /// for everything else.
+    #[default]
+    Preview,
+    /// A paragraph with a link in it, like any other text.
+    Inline,
+}
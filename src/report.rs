@@ -0,0 +1,110 @@
+//! Notes on what didn't make it from Markdown to Notion intact.
+
+use markdown::mdast::Node;
+use markdown::unist::Position;
+use serde::Serialize;
+
+/// Something in the Markdown that we couldn't convert faithfully.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Diagnostic {
+    /// The kind of Markdown node, named the way mdast names it: `html`, `imageReference`, and so on.
+    pub node: &'static str,
+    /// Where the node starts in the source, as line and column. Both count from 1.
+    pub position: Option<(usize, usize)>,
+    pub outcome: Outcome,
+}
+
+/// What happened to the node.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "outcome", content = "detail", rename_all = "snake_case")]
+pub enum Outcome {
+    /// Left out of the page entirely.
+    Dropped,
+    /// Shown as its source text in a plain-text code block.
+    DegradedToCode,
+    /// Kept, but plainer than the Markdown asked for; the detail says how.
+    Degraded(String),
+    /// Some of the content was cut off; the detail says what.
+    Truncated(String),
+    /// Points at a definition, footnote, or heading that isn't in the document.
+    Unresolved(String),
+    /// The URL was changed so that Notion would accept it; the detail has both URLs.
+    Rewritten(String),
+    /// Notion wouldn't accept the URL, so the link went and its text stayed. For an image,
+    /// only the alt text is left. The detail says what was wrong.
+    Unlinked(String),
+    /// Couldn't be parsed, so none of it was used. The detail is the parser's complaint.
+    Unparsed(String),
+}
+
+impl Diagnostic {
+    pub(crate) fn new(node: &'static str, position: Option<&Position>, outcome: Outcome) -> Self {
+        Diagnostic {
+            node,
+            position: position.map(|pos| (pos.start.line, pos.start.column)),
+            outcome,
+        }
+    }
+
+    pub(crate) fn for_node(node: &Node, outcome: Outcome) -> Self {
+        Diagnostic::new(node_kind(node), node.position(), outcome)
+    }
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some((line, column)) = self.position {
+            write!(f, "{line}:{column}: ")?;
+        }
+        match &self.outcome {
+            Outcome::Dropped => write!(f, "{} dropped", self.node),
+            Outcome::DegradedToCode => write!(f, "{} shown as a code block", self.node),
+            Outcome::Degraded(detail) => write!(f, "{} degraded: {detail}", self.node),
+            Outcome::Truncated(detail) => write!(f, "{} truncated: {detail}", self.node),
+            Outcome::Unresolved(target) => write!(f, "{} points at missing {target}", self.node),
+            Outcome::Rewritten(detail) => write!(f, "{} URL rewritten: {detail}", self.node),
+            Outcome::Unlinked(reason) => write!(f, "{} URL removed because {reason}", self.node),
+            Outcome::Unparsed(reason) => write!(f, "{} ignored: {reason}", self.node),
+        }
+    }
+}
+
+/// The mdast name for this kind of node.
+fn node_kind(node: &Node) -> &'static str {
+    match node {
+        Node::Root(_) => "root",
+        Node::BlockQuote(_) => "blockquote",
+        Node::FootnoteDefinition(_) => "footnoteDefinition",
+        Node::MdxJsxFlowElement(_) => "mdxJsxFlowElement",
+        Node::List(_) => "list",
+        Node::MdxjsEsm(_) => "mdxjsEsm",
+        Node::Toml(_) => "toml",
+        Node::Yaml(_) => "yaml",
+        Node::Break(_) => "break",
+        Node::InlineCode(_) => "inlineCode",
+        Node::InlineMath(_) => "inlineMath",
+        Node::Delete(_) => "delete",
+        Node::Emphasis(_) => "emphasis",
+        Node::MdxTextExpression(_) => "mdxTextExpression",
+        Node::FootnoteReference(_) => "footnoteReference",
+        Node::Html(_) => "html",
+        Node::Image(_) => "image",
+        Node::ImageReference(_) => "imageReference",
+        Node::MdxJsxTextElement(_) => "mdxJsxTextElement",
+        Node::Link(_) => "link",
+        Node::LinkReference(_) => "linkReference",
+        Node::Strong(_) => "strong",
+        Node::Text(_) => "text",
+        Node::Code(_) => "code",
+        Node::Math(_) => "math",
+        Node::MdxFlowExpression(_) => "mdxFlowExpression",
+        Node::Heading(_) => "heading",
+        Node::Table(_) => "table",
+        Node::ThematicBreak(_) => "thematicBreak",
+        Node::TableRow(_) => "tableRow",
+        Node::TableCell(_) => "tableCell",
+        Node::ListItem(_) => "listItem",
+        Node::Definition(_) => "definition",
+        Node::Paragraph(_) => "paragraph",
+    }
+}
@@ -1,79 +1,376 @@
-//! Wrappers around the Notion client that retry requests that get 409s.
-//!
+//! Wrappers around the Notion client that retry requests that fail in ways that
+//! might not happen twice: conflicts, rate limits, server trouble, lost connections.
+//! Which of those a request retries depends on whether it's safe to send it twice.
 
-use miette::{IntoDiagnostic, Result};
+use std::collections::hash_map::RandomState;
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+use miette::{miette, IntoDiagnostic, Result};
 use notion_client::endpoints::blocks::append::request::AppendBlockChildrenRequest;
+use notion_client::endpoints::blocks::update::request::UpdateABlockRequest;
 use notion_client::endpoints::pages::create::request::CreateAPageRequest;
-use notion_client::endpoints::Client;
 use notion_client::objects::block::Block;
 use notion_client::objects::page::Page as NotionPage;
 use owo_colors::OwoColorize;
 
-/// The most we'll retry a 409 conflicted request
-static MAX_RETRIES: u8 = 5;
+use crate::{ApiError, NotionApi};
+
+/// How often and how patiently to retry a failed request. Waits grow exponentially,
+/// with jitter, unless Notion tells us how long to wait with `Retry-After`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// How many times to try again after the first attempt fails.
+    pub retries: u32,
+    /// The longest wait before the first retry. Each retry after that doubles it.
+    pub base_delay: Duration,
+    /// No wait is longer than this, not even one Notion asks for with `Retry-After`.
+    pub max_delay: Duration,
+}
 
-/// Time to delay between requests
-static NOTION_DELAY_MS: u64 = 200;
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            retries: 5,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+        }
+    }
+}
 
-pub async fn do_create(notion: &Client, request: &CreateAPageRequest, retry: u8) -> Result<NotionPage> {
-    if retry > 0 {
-        println!("    do_create(); retry={}", retry.bold());
+impl RetryPolicy {
+    /// How long to wait before retrying, after `attempt` retries so far.
+    pub fn delay(&self, attempt: u32, error: &ApiError) -> Duration {
+        if let Some(asked) = error.retry_after() {
+            return asked.min(self.max_delay);
+        }
+        let ceiling = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_delay);
+        // Half of it is random, so that requests that failed together don't all
+        // come back together.
+        let half = ceiling / 2;
+        half + half.mul_f64(jitter())
     }
-    let next_retry = retry + 1;
-    match notion.pages.create_a_page(request.clone()).await {
-        Ok(resp) => Ok(resp),
-        Err(e) => match e {
-            notion_client::NotionClientError::InvalidStatusCode { ref error } => {
-                if error.status == 409 && retry < MAX_RETRIES {
-                    println!("    do_create() got {}; retrying", 409.bold());
-                    Box::pin(do_create(notion, request, next_retry)).await
-                } else {
-                    Err(e).into_diagnostic()
+}
+
+/// A number between 0 and 1 that's different every time, without a dependency.
+fn jitter() -> f64 {
+    RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64
+}
+
+/// Make a request until it works, fails in a way `retryable` says not to repeat, or runs
+/// out of retries.
+async fn with_retries<T, F, Fut>(
+    notion: &impl NotionApi,
+    what: &str,
+    retryable: fn(&ApiError) -> bool,
+    mut request: F,
+) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, ApiError>>,
+{
+    let policy = notion.retry_policy();
+    let limiter = notion.rate_limiter();
+    let mut attempt = 0;
+    loop {
+        if let Some(limiter) = limiter {
+            limiter.acquire().await;
+        }
+        let error = match request().await {
+            Ok(response) => {
+                if let Some(limiter) = limiter {
+                    limiter.succeeded();
                 }
+                return Ok(response);
             }
-            _ => Err(e).into_diagnostic(),
-        },
+            Err(error) => error,
+        };
+        if let (Some(limiter), Some(429)) = (limiter, error.status()) {
+            limiter.throttled(error.retry_after().map(|asked| asked.min(policy.max_delay)));
+        }
+        if !retryable(&error) {
+            return Err(error).into_diagnostic();
+        }
+        if attempt >= policy.retries {
+            return Err(miette!("{what}() gave up after {attempt} retries: {error}"));
+        }
+        let wait = policy.delay(attempt, &error);
+        let why = error
+            .status()
+            .map_or_else(|| "a transport error".to_string(), |status| status.to_string());
+        println!(
+            "    {what}() got {}; retry {} in {}ms",
+            why.bold(),
+            attempt + 1,
+            wait.as_millis()
+        );
+        tokio::time::sleep(wait).await;
+        attempt += 1;
     }
 }
 
+/// A create that Notion applied before the connection dropped would make a second page,
+/// so this only retries failures where Notion says it did nothing.
+pub async fn do_create(notion: &impl NotionApi, request: &CreateAPageRequest) -> Result<NotionPage> {
+    with_retries(notion, "do_create", ApiError::is_retryable, || {
+        notion.create_page(request.clone())
+    })
+    .await
+}
+
+/// Like creates, appends that went through twice would duplicate blocks.
 pub async fn do_append(
-    notion: &Client,
+    notion: &impl NotionApi,
     parent_id: &str,
     slice: &[Block],
     after: Option<String>,
-    retry: u8,
 ) -> Result<Vec<Block>> {
-    if retry > 0 {
-        println!("    do_append(); retry={}", retry.bold());
-        // println!(
-        //     "    doing append; parent_id={parent_id}; after_id={after:?}; children={}; retries: {}",
-        //     slice.len(),
-        //     retry.bold()
-        // );
-    }
-    let next_retry = retry + 1;
     if slice.is_empty() {
         return Ok(Vec::new());
     }
-    let children = slice.to_vec();
-    // We're having 409 problems at the speed we're making API requests right now. It is to lol.
-    tokio::time::sleep(std::time::Duration::from_millis(NOTION_DELAY_MS)).await;
     let append_req = AppendBlockChildrenRequest {
         children: slice.to_vec(),
-        after: after.clone(),
+        after,
     };
-    match notion.blocks.append_block_children(parent_id, append_req).await {
-        Ok(response) => Ok(response.results),
-        Err(e) => match e {
-            notion_client::NotionClientError::InvalidStatusCode { ref error } => {
-                if error.status == 409 && retry < MAX_RETRIES {
-                    println!("    do_append() got {}; retrying", 409.bold());
-                    Box::pin(do_append(notion, parent_id, children.as_slice(), after, next_retry)).await
-                } else {
-                    Err(e).into_diagnostic()
-                }
+    with_retries(notion, "do_append", ApiError::is_retryable, || {
+        notion.append_children(parent_id, append_req.clone())
+    })
+    .await
+}
+
+/// Setting a block to the same thing twice does no harm, so updates retry server errors
+/// and lost connections too.
+pub async fn do_update(notion: &impl NotionApi, block_id: &str, block: &Block) -> Result<Option<Block>> {
+    let update_req = UpdateABlockRequest {
+        block: Some(block.clone()),
+        archived: None,
+    };
+    with_retries(notion, "do_update", ApiError::is_retryable_if_idempotent, || {
+        notion.update_block(block_id, update_req.clone())
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Instant;
+
+    use notion_client::objects::parent::Parent;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    use super::*;
+    use crate::NotionHttp;
+
+    /// What the stand-in does with each request it gets, in order.
+    enum Reply {
+        Status(u16, &'static str, String),
+        Hangup,
+    }
+
+    fn page_json() -> String {
+        let parent = Parent::PageId {
+            page_id: "parent".to_string(),
+        };
+        let page = crate::fake::page_object("00000001-0000-4000-8000-000000000001", parent, Default::default())
+            .expect("the fake's page should deserialize");
+        serde_json::to_string(&page).expect("pages serialize")
+    }
+
+    fn block_json() -> String {
+        let mut block = crate::convert("words").remove(0);
+        block.id = Some("00000002-0000-4000-8000-000000000002".to_string());
+        serde_json::to_string(&block).expect("blocks serialize")
+    }
+
+    fn error_json(status: u16, code: &str) -> String {
+        serde_json::json!({ "object": "error", "status": status, "code": code, "message": "stand-in" }).to_string()
+    }
+
+    /// Read one request, headers and body, so the client sees the whole thing sent.
+    async fn read_request(socket: &mut TcpStream) {
+        let mut seen = Vec::new();
+        let mut buf = [0u8; 4096];
+        loop {
+            let read = socket.read(&mut buf).await.unwrap_or(0);
+            if read == 0 {
+                return;
+            }
+            seen.extend_from_slice(&buf[..read]);
+            let text = String::from_utf8_lossy(&seen);
+            let Some(end) = text.find("\r\n\r\n") else { continue };
+            let length = text[..end]
+                .lines()
+                .find_map(|line| {
+                    line.to_ascii_lowercase()
+                        .strip_prefix("content-length:")
+                        .map(str::to_owned)
+                })
+                .and_then(|value| value.trim().parse::<usize>().ok())
+                .unwrap_or(0);
+            if seen.len() >= end + 4 + length {
+                return;
             }
-            _ => Err(e).into_diagnostic(),
-        },
+        }
+    }
+
+    /// A local HTTP server that answers each request with the next reply, and counts them.
+    async fn stand_in(replies: Vec<Reply>) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("binding to localhost");
+        let address = listener.local_addr().expect("a bound listener has an address");
+        let count = Arc::new(AtomicUsize::new(0));
+        let counter = count.clone();
+        tokio::spawn(async move {
+            for reply in replies {
+                let Ok((mut socket, _)) = listener.accept().await else {
+                    return;
+                };
+                read_request(&mut socket).await;
+                counter.fetch_add(1, Ordering::SeqCst);
+                let Reply::Status(status, headers, body) = reply else {
+                    continue; // dropping the socket hangs up
+                };
+                let response = format!(
+                    "HTTP/1.1 {status} Whatever\r\ncontent-length: {}\r\nconnection: close\r\n{headers}\r\n{body}",
+                    body.len()
+                );
+                let _ = socket.write_all(response.as_bytes()).await;
+                let _ = socket.shutdown().await;
+            }
+        });
+        (format!("http://{address}/v1"), count)
+    }
+
+    fn client(base: &str, retries: u32) -> NotionHttp {
+        NotionHttp::new("not-a-real-key")
+            .expect("a client")
+            .with_base_url(base)
+            .with_retries(RetryPolicy {
+                retries,
+                base_delay: Duration::from_millis(2),
+                max_delay: Duration::from_millis(10),
+            })
+            .with_rate_limit(100.0)
+    }
+
+    #[tokio::test]
+    async fn retries_each_failure_mode() {
+        let (base, count) = stand_in(vec![
+            Reply::Status(429, "retry-after: 1\r\n", error_json(429, "rate_limited")),
+            Reply::Status(502, "", "<html>Bad Gateway</html>".to_string()),
+            Reply::Status(503, "", error_json(503, "service_unavailable")),
+            Reply::Status(500, "", error_json(500, "internal_server_error")),
+            Reply::Status(409, "", error_json(409, "conflict_error")),
+            Reply::Hangup,
+            Reply::Status(200, "", block_json()),
+        ])
+        .await;
+        // Long enough that the one-second Retry-After isn't cut short.
+        let notion = client(base.as_str(), 6).with_retries(RetryPolicy {
+            retries: 6,
+            base_delay: Duration::from_millis(2),
+            max_delay: Duration::from_secs(2),
+        });
+        let started = Instant::now();
+        let block = crate::convert("words").remove(0);
+        let updated = do_update(&notion, "a-block", &block)
+            .await
+            .expect("the last try should work")
+            .expect("the response is a block");
+        assert_eq!(updated.id.as_deref(), Some("00000002-0000-4000-8000-000000000002"));
+        assert_eq!(count.load(Ordering::SeqCst), 7);
+        assert!(started.elapsed() >= Duration::from_secs(1), "Retry-After was ignored");
+    }
+
+    #[tokio::test]
+    async fn creates_only_retry_what_notion_turned_away() {
+        let (base, count) = stand_in(vec![
+            Reply::Status(429, "", error_json(429, "rate_limited")),
+            Reply::Status(409, "", error_json(409, "conflict_error")),
+            Reply::Status(503, "", error_json(503, "service_unavailable")),
+            Reply::Status(200, "", page_json()),
+        ])
+        .await;
+        let request = CreateAPageRequest::default();
+        let page = do_create(&client(base.as_str(), 6), &request)
+            .await
+            .expect("the last try should work");
+        assert_eq!(page.id, "00000001-0000-4000-8000-000000000001");
+        assert_eq!(count.load(Ordering::SeqCst), 4);
+
+        // Notion might have made the page before the connection dropped.
+        let (base, count) = stand_in(vec![Reply::Hangup, Reply::Status(200, "", page_json())]).await;
+        assert!(do_create(&client(base.as_str(), 6), &request).await.is_err());
+        assert_eq!(
+            count.load(Ordering::SeqCst),
+            1,
+            "a second page is worse than a failed one"
+        );
+
+        let (base, count) = stand_in(vec![
+            Reply::Status(502, "", "<html>Bad Gateway</html>".to_string()),
+            Reply::Status(200, "", page_json()),
+        ])
+        .await;
+        assert!(do_create(&client(base.as_str(), 6), &request).await.is_err());
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn gives_up() {
+        let (base, count) = stand_in(vec![Reply::Status(400, "", error_json(400, "validation_error"))]).await;
+        let request = CreateAPageRequest::default();
+        assert!(do_create(&client(base.as_str(), 6), &request).await.is_err());
+        assert_eq!(count.load(Ordering::SeqCst), 1, "a 400 isn't worth retrying");
+
+        let unavailable = || Reply::Status(503, "", error_json(503, "service_unavailable"));
+        let (base, count) = stand_in(vec![unavailable(), unavailable(), unavailable()]).await;
+        let error = do_create(&client(base.as_str(), 2), &request)
+            .await
+            .expect_err("three 503s with two retries should fail");
+        assert!(error.to_string().contains("gave up after 2 retries"), "{error}");
+        assert_eq!(count.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn backs_off_with_jitter() {
+        let policy = RetryPolicy::default();
+        let busy = ApiError::Status {
+            status: 503,
+            code: "service_unavailable".to_string(),
+            message: String::new(),
+            retry_after: None,
+        };
+        for attempt in 0..4 {
+            let ceiling = policy.base_delay * 2u32.pow(attempt);
+            let delay = policy.delay(attempt, &busy);
+            assert!(
+                delay >= ceiling / 2 && delay <= ceiling,
+                "{delay:?} for attempt {attempt}"
+            );
+        }
+        assert!(policy.delay(20, &busy) <= policy.max_delay);
+
+        let limited = ApiError::Status {
+            status: 429,
+            code: "rate_limited".to_string(),
+            message: String::new(),
+            retry_after: Some(Duration::from_secs(7)),
+        };
+        assert_eq!(policy.delay(0, &limited), Duration::from_secs(7));
+
+        let stalling = ApiError::Status {
+            status: 429,
+            code: "rate_limited".to_string(),
+            message: String::new(),
+            retry_after: Some(Duration::from_secs(86_400)),
+        };
+        assert_eq!(policy.delay(0, &stalling), policy.max_delay);
     }
 }
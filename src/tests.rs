@@ -82,8 +82,15 @@ pub fn debug_print(block: &Block) {
 #[cfg(test)]
 mod a {
     use notion_client::objects::block::*;
+    use notion_client::objects::file::File;
+    use notion_client::objects::rich_text::{Annotations, RichText};
 
-    use crate::convert;
+    use proptest::prelude::*;
+
+    use crate::{
+        blocks_to_markdown, convert, convert_to_json, convert_with, convert_with_report, ConvertOptions, Diagnostic,
+        FootnoteStyle, HeadingPolicy, HtmlPolicy, Outcome, StandaloneLinks,
+    };
 
     #[test]
     fn rich_text() {
@@ -101,6 +108,93 @@ mod a {
         assert_eq!(paragraph.rich_text.len(), 9);
     }
 
+    #[test]
+    fn nested_rich_text() {
+        let input = "**bold _and italic_** then *[a link](https://example.com)* and **`code`**";
+        let result = convert(input);
+        let block = result.first().expect("we really expected a paragraph here");
+        let paragraph = match &block.block_type {
+            BlockType::Paragraph { paragraph } => paragraph,
+            _ => {
+                panic!("expected a paragraph");
+            }
+        };
+        let runs: Vec<(String, Annotations, Option<String>)> = paragraph
+            .rich_text
+            .iter()
+            .map(|xs| match xs {
+                RichText::Text { text, annotations, .. } => (
+                    text.content.clone(),
+                    annotations.clone().unwrap_or_default(),
+                    text.link.as_ref().map(|link| link.url.clone()),
+                ),
+                _ => panic!("expected only text runs"),
+            })
+            .collect();
+        assert_eq!(runs.len(), 6);
+        assert_eq!(runs[0].0, "bold ");
+        assert!(runs[0].1.bold && !runs[0].1.italic);
+        assert_eq!(runs[1].0, "and italic");
+        assert!(runs[1].1.bold && runs[1].1.italic);
+        assert_eq!(runs[3].0, "a link");
+        assert!(runs[3].1.italic);
+        assert_eq!(runs[3].2.as_deref(), Some("https://example.com"));
+        assert_eq!(runs[5].0, "code");
+        assert!(runs[5].1.bold && runs[5].1.code);
+    }
+
+    #[test]
+    fn hard_breaks_are_newlines() {
+        let result = convert("line one  \n**line two\\\nline three**\n");
+        let paragraph = match &result[0].block_type {
+            BlockType::Paragraph { paragraph } => paragraph,
+            _ => {
+                panic!("expected a paragraph");
+            }
+        };
+        let text: String = paragraph
+            .rich_text
+            .iter()
+            .filter_map(|xs| match xs {
+                RichText::Text { text, .. } => Some(text.content.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(text, "line one\nline two\nline three");
+        match &paragraph.rich_text[3] {
+            RichText::Text { annotations, .. } => {
+                assert!(annotations.as_ref().is_some_and(|style| style.bold));
+            }
+            _ => panic!("expected only text runs"),
+        }
+    }
+
+    #[test]
+    fn long_styled_text_is_split() {
+        let input = format!("[**{}end**](https://example.com)", "word ".repeat(1000));
+        let result = convert(input.as_str());
+        let paragraph = match &result[0].block_type {
+            BlockType::Paragraph { paragraph } => paragraph,
+            _ => {
+                panic!("expected a paragraph");
+            }
+        };
+        assert_eq!(paragraph.rich_text.len(), 3);
+        paragraph.rich_text.iter().for_each(|xs| match xs {
+            RichText::Text {
+                text,
+                annotations,
+                href,
+                ..
+            } => {
+                assert!(text.content.len() <= 2000);
+                assert!(annotations.as_ref().is_some_and(|style| style.bold));
+                assert_eq!(href.as_deref(), Some("https://example.com"));
+            }
+            _ => panic!("expected only text runs"),
+        });
+    }
+
     #[test]
     fn bulleted_list() {
         let input = include_str!("../fixtures/bulleted_list.md");
@@ -178,6 +272,149 @@ mod a {
         assert_eq!(sublist_3.len(), 3);
     }
 
+    #[test]
+    fn task_lists() {
+        let input = include_str!("../fixtures/task_list.md");
+        let blocks = convert(input);
+        assert_eq!(blocks.len(), 4);
+        let checked: Vec<Option<bool>> = blocks
+            .iter()
+            .map(|xs| match &xs.block_type {
+                BlockType::ToDo { to_do } => to_do.checked,
+                _ => None,
+            })
+            .collect();
+        assert_eq!(checked, vec![Some(false), Some(true), None, Some(true)]);
+
+        let second = match &blocks[1].block_type {
+            BlockType::ToDo { to_do } => to_do,
+            _ => {
+                panic!("expected a to-do block");
+            }
+        };
+        let subtasks = second.children.as_ref().expect("the second task should have subtasks");
+        assert_eq!(subtasks.len(), 2);
+        assert!(subtasks
+            .iter()
+            .all(|xs| matches!(xs.block_type, BlockType::ToDo { .. })));
+    }
+
+    #[test]
+    fn block_quotes() {
+        let input = include_str!("../fixtures/block_quotes.md");
+        let blocks = convert(input);
+        assert_eq!(blocks.len(), 2);
+        let short = match &blocks[0].block_type {
+            BlockType::Quote { quote } => quote,
+            _ => {
+                panic!("expected a quote");
+            }
+        };
+        assert_eq!(short.rich_text.len(), 1);
+        assert!(short.children.is_none());
+
+        let long = match &blocks[1].block_type {
+            BlockType::Quote { quote } => quote,
+            _ => {
+                panic!("expected a quote");
+            }
+        };
+        assert_eq!(
+            long.rich_text[0].plain_text().as_deref(),
+            Some("The first paragraph of a longer quote.")
+        );
+        let children = long.children.as_ref().expect("the long quote should have children");
+        assert_eq!(children.len(), 3);
+        assert!(matches!(children[0].block_type, BlockType::Paragraph { .. }));
+        assert!(matches!(children[1].block_type, BlockType::BulletedListItem { .. }));
+        assert!(matches!(children[2].block_type, BlockType::Code { .. }));
+    }
+
+    #[test]
+    fn admonitions() {
+        let input = include_str!("../fixtures/admonitions.md");
+        let blocks = convert(input);
+        // The warning's list follows it when we can't nest it inside the callout.
+        assert_eq!(blocks.len(), 6);
+        let callouts: Vec<&CalloutValue> = blocks
+            .iter()
+            .filter_map(|xs| match &xs.block_type {
+                BlockType::Callout { callout } => Some(callout),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(callouts.len(), 3);
+        assert_eq!(callouts[0].color, TextColor::BlueBackground);
+        assert_eq!(
+            callouts[0].rich_text[0].plain_text().as_deref(),
+            Some("Useful information that users should know, even when skimming content.")
+        );
+        assert_eq!(callouts[1].color, TextColor::YellowBackground);
+        assert_eq!(callouts[2].color, TextColor::GreenBackground);
+        assert_eq!(callouts[2].rich_text[0].plain_text().as_deref(), Some("Shortcut\nRun "));
+        assert!(matches!(blocks[2].block_type, BlockType::BulletedListItem { .. }));
+        assert!(matches!(blocks[5].block_type, BlockType::Quote { .. }));
+        assert!(blocks.iter().all(|xs| xs.id.is_none()));
+
+        // As JSON, the list is where Notion will put it: inside the warning.
+        let (json, _diagnostics) = convert_to_json(input, &ConvertOptions::default());
+        let kinds: Vec<&str> = json
+            .as_array()
+            .expect("an array of blocks")
+            .iter()
+            .filter_map(|xs| xs["type"].as_str())
+            .collect();
+        assert_eq!(kinds, vec!["callout", "callout", "callout", "quote"]);
+        let body = json[1]["callout"]["children"]
+            .as_array()
+            .expect("the warning has a body");
+        assert_eq!(body.len(), 2);
+        assert!(body.iter().all(|xs| xs["type"] == "bulleted_list_item"));
+        assert!(json[0]["callout"].get("children").is_none());
+    }
+
+    #[test]
+    fn footnotes() {
+        let input = include_str!("../fixtures/footnotes.md");
+        let blocks = convert(input);
+        // paragraph, two list items, heading, paragraph, the footnotes heading,
+        // two footnotes, and the second paragraph of the second footnote
+        assert_eq!(blocks.len(), 9);
+        assert!(blocks.iter().all(|xs| xs.id.is_none()));
+        match &blocks[5].block_type {
+            BlockType::Heading2 { heading_2 } => {
+                assert_eq!(heading_2.rich_text[0].plain_text().as_deref(), Some("Footnotes"));
+            }
+            _ => panic!("expected the footnotes heading"),
+        }
+        assert!(matches!(blocks[6].block_type, BlockType::Callout { .. }));
+        assert!(matches!(blocks[7].block_type, BlockType::Callout { .. }));
+        assert!(matches!(blocks[8].block_type, BlockType::Paragraph { .. }));
+
+        let paragraph = match &blocks[0].block_type {
+            BlockType::Paragraph { paragraph } => paragraph,
+            _ => panic!("expected a paragraph"),
+        };
+        let hrefs: Vec<&str> = paragraph
+            .rich_text
+            .iter()
+            .filter_map(|xs| match xs {
+                RichText::Text { href, .. } => href.as_deref(),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(hrefs, vec!["#fn-rotation", "#fn-why"]);
+
+        let missing = match &blocks[2].block_type {
+            BlockType::BulletedListItem { bulleted_list_item } => bulleted_list_item,
+            _ => panic!("expected a list item"),
+        };
+        assert!(missing.rich_text.iter().all(|xs| match xs {
+            RichText::Text { href, .. } => href.is_none(),
+            _ => true,
+        }));
+    }
+
     #[test]
     fn headers() {
         let input = include_str!("../fixtures/headers_and_grafs.md");
@@ -228,19 +465,911 @@ mod a {
         assert!(matches!(table.block_type, BlockType::Table { .. }));
     }
 
+    #[test]
+    fn fragment_links() {
+        let input = include_str!("../fixtures/fragment_links.md");
+        let blocks = convert(input);
+        assert_eq!(blocks.len(), 8);
+        assert!(blocks.iter().all(|xs| xs.id.is_none()));
+        let hrefs = |block: &Block| -> Vec<String> {
+            let rich_text = match &block.block_type {
+                BlockType::Paragraph { paragraph } => &paragraph.rich_text,
+                _ => panic!("expected a paragraph"),
+            };
+            rich_text
+                .iter()
+                .filter_map(|xs| match xs {
+                    RichText::Text { href, .. } => href.clone(),
+                    _ => None,
+                })
+                .collect()
+        };
+        // The link to the missing section is plain text.
+        assert_eq!(hrefs(&blocks[1]), vec!["#setup", "#deploy-steps"]);
+        assert_eq!(hrefs(&blocks[7]), vec!["#setup-1"]);
+    }
+
+    #[test]
+    fn conversion_reports() {
+        let input =
+            "<div>raw</div>\n\nA hard  \nbreak and <blink>inline</blink> html.\n\n| a | b |\n|---|---|\n| 1 | 2 | 3 |\n";
+        let (blocks, diagnostics) = convert_with_report(input, &ConvertOptions::default());
+        assert_eq!(blocks.len(), 3);
+        let truncated = Outcome::Truncated("3 cells in a 2-column table".to_string());
+        let expected = vec![
+            Diagnostic {
+                node: "html",
+                position: Some((1, 1)),
+                outcome: Outcome::DegradedToCode,
+            },
+            Diagnostic {
+                node: "html",
+                position: Some((4, 11)),
+                outcome: Outcome::Dropped,
+            },
+            Diagnostic {
+                node: "html",
+                position: Some((4, 24)),
+                outcome: Outcome::Dropped,
+            },
+            Diagnostic {
+                node: "tableRow",
+                position: Some((8, 1)),
+                outcome: truncated,
+            },
+        ];
+        assert_eq!(diagnostics, expected);
+        let text: String = match &blocks[1].block_type {
+            BlockType::Paragraph { paragraph } => paragraph
+                .rich_text
+                .iter()
+                .filter_map(|xs| match xs {
+                    RichText::Text { text, .. } => Some(text.content.as_str()),
+                    _ => None,
+                })
+                .collect(),
+            _ => panic!("expected a paragraph"),
+        };
+        assert_eq!(text, "A hard\nbreak and inline html.");
+
+        let (_blocks, diagnostics) =
+            convert_with_report(include_str!("../fixtures/task_list.md"), &ConvertOptions::default());
+        assert!(diagnostics.is_empty());
+    }
+
+    #[test]
+    fn inline_images() {
+        let input = include_str!("../fixtures/inline_images.md");
+        let (blocks, diagnostics) = convert_with_report(input, &ConvertOptions::default());
+        assert!(diagnostics.is_empty());
+        let kinds: Vec<&str> = blocks
+            .iter()
+            .map(|xs| match &xs.block_type {
+                BlockType::Paragraph { .. } => "paragraph",
+                BlockType::Image { .. } => "image",
+                _ => "other",
+            })
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                "paragraph",
+                "image",
+                "paragraph",
+                "paragraph", // text, image, caption, text
+                "image",     // no alt text, so no caption
+                "paragraph",
+                "image",
+                "paragraph",
+                "image",
+                "paragraph", // text and two captioned images
+            ]
+        );
+        match &blocks[1].block_type {
+            BlockType::Image { image } => match &image.file_type {
+                File::External { external } => assert_eq!(external.url, "https://example.com/login.png"),
+                _ => panic!("expected an external image"),
+            },
+            _ => panic!("expected an image"),
+        }
+        match &blocks[2].block_type {
+            BlockType::Paragraph { paragraph } => {
+                assert_eq!(paragraph.rich_text[0].plain_text().as_deref(), Some("The login screen"));
+            }
+            _ => panic!("expected a caption"),
+        }
+        match &blocks[8].block_type {
+            BlockType::Image { image } => match &image.file_type {
+                File::External { external } => assert_eq!(external.url, "https://example.com/one.png"),
+                _ => panic!("expected an external image"),
+            },
+            _ => panic!("expected an image"),
+        }
+    }
+
+    #[test]
+    fn html() {
+        let input = include_str!("../fixtures/html.md");
+        let (blocks, diagnostics) = convert_with_report(input, &ConvertOptions::default());
+        assert_eq!(blocks.len(), 5);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].outcome, Outcome::DegradedToCode);
+
+        let runs = match &blocks[0].block_type {
+            BlockType::Paragraph { paragraph } => &paragraph.rich_text,
+            _ => panic!("expected a paragraph"),
+        };
+        let find = |wanted: &str| {
+            runs.iter()
+                .find_map(|xs| match xs {
+                    RichText::Text { text, annotations, .. } if text.content == wanted => {
+                        Some((annotations.clone().unwrap_or_default(), text.link.clone()))
+                    }
+                    _ => None,
+                })
+                .unwrap_or_else(|| panic!("no run with the text {wanted}"))
+        };
+        assert!(find("Ctrl").0.code);
+        assert!(find("stop").0.underline);
+        assert_eq!(
+            find("the logs").0.color,
+            notion_client::objects::rich_text::TextColor::YellowBackground
+        );
+        find("\n");
+        find("₂");
+        find("²");
+        assert_eq!(
+            find("the docs").1.map(|link| link.url),
+            Some("https://example.com/docs".to_string())
+        );
+
+        match &blocks[1].block_type {
+            BlockType::Toggle { toggle } => {
+                let summary: String = toggle.rich_text.iter().filter_map(|xs| xs.plain_text()).collect();
+                assert_eq!(summary, "Why does this happen?");
+                let children = toggle.children.as_ref().expect("the toggle should have children");
+                assert_eq!(children.len(), 4);
+                assert!(matches!(children[1].block_type, BlockType::BulletedListItem { .. }));
+                assert!(matches!(children[3].block_type, BlockType::Toggle { .. }));
+            }
+            _ => panic!("expected a toggle"),
+        }
+        assert!(matches!(blocks[2].block_type, BlockType::Image { .. }));
+        assert!(matches!(blocks[3].block_type, BlockType::Paragraph { .. }));
+        assert!(matches!(blocks[4].block_type, BlockType::Code { .. }));
+    }
+
+    #[test]
+    fn scripts_without_unicode_forms() {
+        let input = "The 1<sup>st</sup> of H<sub>2</sub>O.\n\n<p>The n<sup>th</sup> time</p>\n";
+        let (blocks, diagnostics) = convert_with_report(input, &ConvertOptions::default());
+        let text: String = match &blocks[0].block_type {
+            BlockType::Paragraph { paragraph } => paragraph.rich_text.iter().filter_map(|xs| xs.plain_text()).collect(),
+            _ => panic!("expected a paragraph"),
+        };
+        assert_eq!(text, "The 1st of H₂O.");
+        assert_eq!(
+            diagnostics,
+            vec![
+                Diagnostic {
+                    node: "text",
+                    position: Some((1, 11)),
+                    outcome: Outcome::Degraded("no superscript form for \"st\", so \"st\" is plain text".to_string()),
+                },
+                Diagnostic {
+                    node: "html",
+                    position: Some((3, 1)),
+                    outcome: Outcome::Degraded("no superscript form for \"th\", so \"th\" is plain text".to_string()),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn conversion_options() {
+        let input = include_str!("../fixtures/html.md");
+        let options = ConvertOptions {
+            html: HtmlPolicy::CodeBlock,
+            ..Default::default()
+        };
+        let (blocks, diagnostics) = convert_with_report(input, &options);
+        // The paragraph keeps its text but loses the tags; each HTML block is a code block.
+        assert!(matches!(blocks[0].block_type, BlockType::Paragraph { .. }));
+        assert!(!blocks
+            .iter()
+            .any(|xs| matches!(xs.block_type, BlockType::Toggle { .. })));
+        assert!(diagnostics.iter().any(|xs| xs.outcome == Outcome::DegradedToCode));
+        assert!(diagnostics.iter().any(|xs| xs.outcome == Outcome::Dropped));
+
+        let options = ConvertOptions {
+            html: HtmlPolicy::Drop,
+            ..Default::default()
+        };
+        let (blocks, diagnostics) = convert_with_report(input, &options);
+        assert!(!blocks.iter().any(|xs| matches!(xs.block_type, BlockType::Code { .. })));
+        assert!(!blocks.iter().any(|xs| matches!(xs.block_type, BlockType::Image { .. })));
+        assert!(diagnostics.iter().all(|xs| xs.outcome == Outcome::Dropped));
+
+        let input = include_str!("../fixtures/footnotes.md");
+        let options = ConvertOptions {
+            footnotes: FootnoteStyle::InPlace,
+            ..Default::default()
+        };
+        let blocks = convert_with(input, &options);
+        // No footnotes heading: each footnote sits where it was defined.
+        assert_eq!(blocks.len(), 8);
+        assert!(matches!(blocks[1].block_type, BlockType::Callout { .. }));
+        assert!(matches!(blocks[4].block_type, BlockType::Callout { .. }));
+        assert!(matches!(blocks[6].block_type, BlockType::Heading2 { .. }));
+
+        let input = "![Local](./images/local.png) ![Remote](https://cdn.example.com/remote.png)\n\n\
+            See [the wiki](https://old.example.com/wiki) and [below](#below).\n\n## Below\n";
+        let options = ConvertOptions {
+            image_base: Some("https://example.com/assets/".to_string()),
+            rewrite_link: Some(std::sync::Arc::new(|url: &str| {
+                url.replace("old.example.com", "new.example.com")
+            })),
+            ..Default::default()
+        };
+        let blocks = convert_with(input, &options);
+        let urls: Vec<&str> = blocks
+            .iter()
+            .filter_map(|xs| match &xs.block_type {
+                BlockType::Image { image } => match &image.file_type {
+                    File::External { external } => Some(external.url.as_str()),
+                    _ => None,
+                },
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            urls,
+            vec![
+                "https://example.com/assets/images/local.png",
+                "https://cdn.example.com/remote.png"
+            ]
+        );
+        let links: Vec<Option<String>> = blocks
+            .iter()
+            .filter_map(|xs| match &xs.block_type {
+                BlockType::Paragraph { paragraph } => Some(paragraph),
+                _ => None,
+            })
+            .flat_map(|xs| xs.rich_text.iter())
+            .filter_map(|xs| match xs {
+                RichText::Text { text, href, .. } if text.link.is_some() || href.is_some() => {
+                    Some(text.link.as_ref().map(|link| link.url.clone()).or(href.clone()))
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            links,
+            vec![
+                Some("https://new.example.com/wiki".to_string()),
+                Some("#below".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn math() {
+        let input = include_str!("../fixtures/math.md");
+        let plain_text = |block: &Block| match &block.block_type {
+            BlockType::Paragraph { paragraph } => paragraph
+                .rich_text
+                .iter()
+                .filter_map(|xs| xs.plain_text())
+                .collect::<String>(),
+            _ => panic!("expected a paragraph"),
+        };
+
+        // Math is off by default, so dollar signs are just text.
+        let blocks = convert(input);
+        assert_eq!(blocks.len(), 4);
+        assert_eq!(
+            plain_text(&blocks[0]),
+            "The area of a circle is $\\pi r^2$, and Einstein gave us $$E = mc^2$$."
+        );
+
+        let options = ConvertOptions {
+            math: true,
+            ..Default::default()
+        };
+        let (blocks, diagnostics) = convert_with_report(input, &options);
+        assert!(diagnostics.is_empty());
+        assert_eq!(blocks.len(), 4);
+        let equations: Vec<&str> = match &blocks[0].block_type {
+            BlockType::Paragraph { paragraph } => paragraph
+                .rich_text
+                .iter()
+                .filter_map(|xs| match xs {
+                    RichText::Equation { equation, .. } => Some(equation.expression.as_str()),
+                    _ => None,
+                })
+                .collect(),
+            _ => panic!("expected a paragraph"),
+        };
+        assert_eq!(equations, vec!["\\pi r^2", "E = mc^2"]);
+        // Prices aren't equations, and they come through exactly as written.
+        assert_eq!(
+            plain_text(&blocks[1]),
+            "Lunch was $5 and dinner was $10, so $15 in all. A refund of $3.50, or $ 4 $, never came."
+        );
+        match &blocks[1].block_type {
+            BlockType::Paragraph { paragraph } => assert_eq!(paragraph.rich_text.len(), 1),
+            _ => panic!("expected a paragraph"),
+        }
+        match &blocks[2].block_type {
+            BlockType::Equation { equation } => {
+                assert_eq!(equation.expression, "\\int_0^1 x^2 \\, dx = \\frac{1}{3}");
+            }
+            _ => panic!("expected an equation block"),
+        }
+        assert_eq!(plain_text(&blocks[3]), "Escaped dollars like $20 stay as they are.");
+    }
+
+    #[test]
+    fn urls() {
+        let input = include_str!("../fixtures/urls.md");
+        let links = |blocks: &[Block]| -> Vec<(String, Option<String>)> {
+            blocks
+                .iter()
+                .filter_map(|xs| match &xs.block_type {
+                    BlockType::Paragraph { paragraph } => Some(paragraph),
+                    _ => None,
+                })
+                .flat_map(|xs| xs.rich_text.iter())
+                .filter_map(|xs| match xs {
+                    RichText::Text { text, .. } => Some((text.content.clone(), text.link.clone().map(|xs| xs.url))),
+                    _ => None,
+                })
+                .filter(|(content, _)| !content.trim().is_empty() && content.trim() != ".")
+                .collect()
+        };
+        let linked = |text: &str, url: Option<&str>| (text.to_string(), url.map(str::to_string));
+
+        // Without a base, relative links lose their URLs but keep their text, and so do
+        // links Notion can't take. Nothing is left that would make Notion refuse the page.
+        let (blocks, diagnostics) = convert_with_report(input, &ConvertOptions::default());
+        let found = links(&blocks);
+        assert!(found.contains(&linked("the setup guide", None)));
+        assert!(found.contains(&linked("email us", Some("mailto:ops@example.com"))));
+        assert!(found.contains(&linked("don't", None)));
+        assert!(found.contains(&linked("old report", None)));
+        assert!(found.contains(&linked("this", None)));
+        assert!(found.contains(&linked("Search", Some("https://example.com/search?q=a%20b"))));
+        assert!(found.contains(&linked("The handbook", None)));
+        assert!(!blocks.iter().any(|xs| matches!(xs.block_type, BlockType::Image { .. })));
+        // The alt text of each image is still there.
+        assert!(found.contains(&linked("The rack", None)));
+        assert!(found.contains(&linked("My desk", None)));
+
+        let unlinked = diagnostics
+            .iter()
+            .filter(|xs| matches!(xs.outcome, Outcome::Unlinked(_)))
+            .count();
+        let rewritten = diagnostics
+            .iter()
+            .filter(|xs| matches!(xs.outcome, Outcome::Rewritten(_)))
+            .count();
+        assert_eq!((unlinked, rewritten, diagnostics.len()), (7, 1, 8));
+        assert_eq!(
+            diagnostics[0].to_string(),
+            "1:6: link URL removed because `docs/setup.md` is relative, and there's no base URL"
+        );
+
+        let options = ConvertOptions {
+            base_url: Some("https://wiki.example.com/team".to_string()),
+            ..Default::default()
+        };
+        let (blocks, diagnostics) = convert_with_report(input, &options);
+        let found = links(&blocks);
+        assert!(found.contains(&linked(
+            "the setup guide",
+            Some("https://wiki.example.com/team/docs/setup.md")
+        )));
+        assert!(found.contains(&linked("The handbook", Some("https://wiki.example.com/handbook/"))));
+        assert!(found.contains(&linked("don't", None)));
+        match &blocks[1].block_type {
+            BlockType::Image { image } => match &image.file_type {
+                File::External { external } => {
+                    assert_eq!(external.url, "https://wiki.example.com/team/img/rack.png")
+                }
+                _ => panic!("expected an external image"),
+            },
+            _ => panic!("expected an image"),
+        }
+        assert_eq!(
+            diagnostics[0].outcome,
+            Outcome::Rewritten("`docs/setup.md` became `https://wiki.example.com/team/docs/setup.md`".to_string())
+        );
+        // The javascript link, the empty one, and the local screenshot still can't be fixed.
+        assert_eq!(
+            diagnostics
+                .iter()
+                .filter(|xs| matches!(xs.outcome, Outcome::Unlinked(_)))
+                .count(),
+            3
+        );
+    }
+
+    #[test]
+    fn standalone_links() {
+        let input = include_str!("../fixtures/standalone_links.md");
+        let previews: Vec<(&str, Option<String>)> = convert(input)
+            .iter()
+            .map(|xs| match &xs.block_type {
+                BlockType::Paragraph { .. } => ("paragraph", None),
+                BlockType::Bookmark { bookmark } => ("bookmark", Some(bookmark.url.clone())),
+                BlockType::Embed { embed } => ("embed", Some(embed.url.clone())),
+                BlockType::Video { video } => match &video.file_type {
+                    File::External { external } => ("video", Some(external.url.clone())),
+                    _ => panic!("expected an external video"),
+                },
+                BlockType::Pdf { pdf } => match &pdf.file_type {
+                    File::External { external } => ("pdf", Some(external.url.clone())),
+                    _ => panic!("expected an external PDF"),
+                },
+                _ => ("other", None),
+            })
+            .collect();
+        let preview = |kind, url: &str| (kind, Some(url.to_string()));
+        assert_eq!(
+            previews,
+            vec![
+                ("paragraph", None),
+                preview("video", "https://www.youtube.com/watch?v=dQw4w9WgXcQ"),
+                preview("pdf", "https://example.com/reports/q3.pdf"),
+                preview("embed", "https://www.figma.com/file/abc123/Checkout"),
+                preview("bookmark", "http://www.example.com/blog/launch"),
+                // A link with its own text, a link in a sentence, and an email address.
+                ("paragraph", None),
+                ("paragraph", None),
+                ("paragraph", None),
+            ]
+        );
+
+        let options = ConvertOptions {
+            standalone_links: StandaloneLinks::Inline,
+            ..Default::default()
+        };
+        let blocks = convert_with(input, &options);
+        assert_eq!(blocks.len(), 8);
+        assert!(blocks
+            .iter()
+            .all(|xs| matches!(xs.block_type, BlockType::Paragraph { .. })));
+    }
+
+    #[test]
+    fn table_of_contents() {
+        let input = include_str!("../fixtures/table_of_contents.md");
+        let blocks = convert(input);
+        assert_eq!(
+            block_kinds(&blocks),
+            vec!["toc", "h2", "paragraph", "h2", "paragraph", "h3", "paragraph", "toc"]
+        );
+
+        // A page with a marker keeps its one table of contents.
+        let options = ConvertOptions {
+            auto_toc: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(block_kinds(&convert_with(input, &options)), block_kinds(&blocks));
+
+        let input = "# One\n\n## Two\n\n## Three\n";
+        assert_eq!(
+            block_kinds(&convert_with(input, &options)),
+            vec!["toc", "h1", "h2", "h2"]
+        );
+        let options = ConvertOptions {
+            auto_toc: Some(3),
+            ..Default::default()
+        };
+        assert_eq!(block_kinds(&convert_with(input, &options)), vec!["h1", "h2", "h2"]);
+        assert_eq!(block_kinds(&convert(input)), vec!["h1", "h2", "h2"]);
+    }
+
+    #[test]
+    fn round_trip() {
+        for entry in std::fs::read_dir("fixtures").expect("the fixtures directory is readable") {
+            let path = entry.expect("fixtures are listable").path();
+            let input = std::fs::read_to_string(&path).expect("fixtures are readable");
+            let blocks = convert(input.as_str());
+            let markdown = blocks_to_markdown(&blocks);
+            let again = convert(markdown.as_str());
+            assert_eq!(
+                normalized(&blocks),
+                normalized(&again),
+                "{} came back different",
+                path.display()
+            );
+            assert_eq!(markdown, blocks_to_markdown(&again), "{} isn't stable", path.display());
+        }
+    }
+
+    #[test]
+    fn markdown_export() {
+        let input = r#"# Getting *started*
+
+Some **bold *and italic*** text, `code`, ~~gone~~, and [a link](https://example.com/a_b).
+
+- one
+- two
+  - nested
+
+1. first
+2. second
+
+- [x] done
+- [ ] not yet
+
+> [!WARNING]
+> Mind the gap.
+
+```rust title="main.rs"
+fn main() {}
+```
+
+| a | b |
+| --- | --- |
+| 1 | 2 \| 3 |
+
+---
+
+Escapes: 2 * 3, a_b, #hashtag, a < b, and $5.
+"#;
+        let expected = r#"# Getting *started*
+
+Some **bold *and italic*** text, `code`, ~~gone~~, and [a link](https://example.com/a_b).
+
+- one
+- two
+  - nested
+
+1. first
+2. second
+
+- [x] done
+- [ ] not yet
+
+> [!WARNING]
+> Mind the gap.
+
+```rust title="main.rs"
+fn main() {}
+```
+
+| a | b |
+| --- | --- |
+| 1 | 2 \| 3 |
+
+***
+
+Escapes: 2 \* 3, a\_b, #hashtag, a \< b, and \$5.
+"#;
+        assert_eq!(blocks_to_markdown(&convert(input)), expected);
+    }
+
+    #[test]
+    fn leading_divider_is_not_front_matter() {
+        let blocks = convert("***\n\n# *a*");
+        let markdown = blocks_to_markdown(&blocks);
+        assert_eq!(normalized(&blocks), normalized(&convert(markdown.as_str())));
+    }
+
+    fn document() -> impl Strategy<Value = String> {
+        let word = prop_oneof![
+            "[a-z]{1,8}",
+            "[0-9]{1,3}[.)]?",
+            "[-#>+=*_`~|$<>&@:/.\\\\\\[\\]!]{1,3}",
+            "https?://[a-z]{1,5}\\.com",
+        ];
+        let styled = (word, 0..6usize).prop_map(|(word, style)| match style {
+            0 => format!("*{word}*"),
+            1 => format!("**{word}**"),
+            2 => format!("`{word}`"),
+            3 => format!("~~{word}~~"),
+            _ => word,
+        });
+        let line = prop::collection::vec(styled, 1..8).prop_map(|words| words.join(" "));
+        let block = (line, 0..8usize).prop_map(|(line, kind)| match kind {
+            0 => format!("# {line}"),
+            1 => format!("### {line}"),
+            2 => format!("- {line}"),
+            3 => format!("1. {line}"),
+            4 => format!("> {line}"),
+            5 => format!("```\n{line}\n```"),
+            _ => line,
+        });
+        prop::collection::vec(block, 1..6).prop_map(|blocks| blocks.join("\n\n"))
+    }
+
+    proptest! {
+        #[test]
+        fn round_trip_anything(input in document()) {
+            // The parser itself panics on a few of these, like a fence in a list item
+            // right before an ordered list.
+            let parsed = std::panic::catch_unwind(|| convert(input.as_str()));
+            prop_assume!(parsed.is_ok());
+            let blocks = parsed.expect("checked just above");
+            let markdown = blocks_to_markdown(&blocks);
+            prop_assert_eq!(normalized(&blocks), normalized(&convert(markdown.as_str())), "{}", markdown);
+        }
+    }
+
+    #[test]
+    fn code_blocks() {
+        let body = "let x = 1;\n".repeat(500);
+        let input = format!("```rs title=\"src/main.rs\"\n{body}```\n\n```\nplain\n```\n");
+        let result = convert(input.as_str());
+        assert_eq!(result.len(), 2);
+        let code = match &result[0].block_type {
+            BlockType::Code { code } => code,
+            _ => {
+                panic!("expected a code block");
+            }
+        };
+        assert_eq!(code.language, Language::Rust);
+        assert_eq!(code.caption.len(), 1);
+        assert_eq!(code.caption[0].plain_text().as_deref(), Some("src/main.rs"));
+        assert_eq!(code.rich_text.len(), 3);
+        let joined: String = code.rich_text.iter().filter_map(|xs| xs.plain_text()).collect();
+        assert_eq!(joined, body.trim_end());
+
+        let plain = match &result[1].block_type {
+            BlockType::Code { code } => code,
+            _ => {
+                panic!("expected a code block");
+            }
+        };
+        assert_eq!(plain.language, Language::PlainText);
+        assert!(plain.caption.is_empty());
+    }
+
+    #[test]
+    fn ragged_tables() {
+        let input = include_str!("../fixtures/ragged_table.md");
+        let result = convert(input);
+        assert_eq!(result.len(), 1);
+        let table = match &result[0].block_type {
+            BlockType::Table { table } => table,
+            _ => {
+                panic!("expected a table");
+            }
+        };
+        assert_eq!(table.table_width, 3);
+        assert!(table.has_column_header);
+        assert!(!table.has_row_header);
+        let rows = table.children.as_ref().expect("the table should have rows");
+        assert_eq!(rows.len(), 5);
+        rows.iter().for_each(|xs| match &xs.block_type {
+            BlockType::TableRow { table_row } => assert_eq!(table_row.cells.len(), 3),
+            _ => panic!("expected only table rows"),
+        });
+    }
+
     #[test]
     fn gfm_parsing() {
         let input = include_str!("../fixtures/gfm-test.md");
         let result = convert(input);
         assert_eq!(result.len(), 36);
+        // H5 is deeper than Notion goes, so by default it becomes a bold paragraph.
         let block10 = result[10].clone();
-        eprintln!("{block10:?}");
-        assert!(matches!(block10.block_type, BlockType::Heading3 { .. }));
-        let _heading3 = match block10.block_type {
-            BlockType::Heading3 { heading_3 } => heading_3,
+        let paragraph = match block10.block_type {
+            BlockType::Paragraph { paragraph } => paragraph,
             _ => {
-                panic!("expected heading 3 block type");
+                panic!("expected a paragraph for the H5");
+            }
+        };
+        assert_eq!(paragraph.rich_text[0].plain_text().as_deref(), Some("H5"));
+        assert!(paragraph.rich_text.iter().all(|xs| match xs {
+            RichText::Text { annotations, .. } => annotations.as_ref().is_some_and(|xs| xs.bold),
+            _ => false,
+        }));
+    }
+
+    /// Blocks as JSON, with adjacent text runs that look the same merged into one, and
+    /// the whitespace at either end of a piece of rich text trimmed. Markdown can say
+    /// the same thing with different runs; this is what has to survive a round trip.
+    pub(super) fn normalized(blocks: &[Block]) -> serde_json::Value {
+        fn merge(runs: &[serde_json::Value]) -> Vec<serde_json::Value> {
+            let plain = serde_json::to_value(Annotations::default()).expect("annotations serialize");
+            // Split text at whitespace, so that whitespace can be compared on its own.
+            let pieces = runs.iter().flat_map(|run| match run["text"]["content"].as_str() {
+                Some(content) => content
+                    .chars()
+                    .collect::<Vec<_>>()
+                    .chunk_by(|a, b| a.is_whitespace() == b.is_whitespace())
+                    .map(|chars| {
+                        let mut piece = run.clone();
+                        piece["text"]["content"] = chars.iter().collect::<String>().into();
+                        piece
+                    })
+                    .collect::<Vec<_>>(),
+                None => vec![run.clone()],
+            });
+            let mut merged: Vec<serde_json::Value> = Vec::new();
+            for mut run in pieces {
+                // Markdown has no colors, and leaving annotations out means the defaults.
+                if run["annotations"].is_null() {
+                    run["annotations"] = plain.clone();
+                }
+                if run["annotations"]["color"] == "gray" {
+                    run["annotations"]["color"] = plain["color"].clone();
+                }
+                // Nor do bold and italic show on whitespace.
+                let blank = run["text"]["content"].as_str().is_some_and(|xs| xs.trim().is_empty());
+                if let (true, Some(previous)) = (blank, merged.last()) {
+                    for key in ["bold", "italic"] {
+                        run["annotations"][key] = previous["annotations"][key].clone();
+                    }
+                }
+                let run = &run;
+                let same_style = |previous: &serde_json::Value| {
+                    ["type", "annotations", "href"]
+                        .iter()
+                        .all(|key| previous[key] == run[key])
+                        && previous["text"]["link"] == run["text"]["link"]
+                        && run["type"] == "text"
+                };
+                match merged.last_mut() {
+                    Some(previous) if same_style(previous) => {
+                        let content = format!(
+                            "{}{}",
+                            previous["text"]["content"].as_str().unwrap_or_default(),
+                            run["text"]["content"].as_str().unwrap_or_default()
+                        );
+                        previous["text"]["content"] = content.clone().into();
+                        previous["plain_text"] = content.into();
+                    }
+                    _ => merged.push(run.clone()),
+                }
+            }
+            merged.retain(|xs| xs["type"] != "text" || !xs["text"]["content"].as_str().unwrap_or_default().is_empty());
+            for (idx, end) in [(0, true), (merged.len().saturating_sub(1), false)] {
+                if let Some(content) = merged.get(idx).and_then(|xs| xs["text"]["content"].as_str()) {
+                    let trimmed = if end { content.trim_start() } else { content.trim_end() }.to_string();
+                    merged[idx]["text"]["content"] = trimmed.clone().into();
+                    merged[idx]["plain_text"] = trimmed.into();
+                }
+            }
+            merged
+        }
+        fn walk(value: &mut serde_json::Value) {
+            match value {
+                serde_json::Value::Object(map) => {
+                    // No children and an empty list of them are the same thing.
+                    map.retain(|key, child| key != "children" || child.as_array().is_none_or(|xs| !xs.is_empty()));
+                    for (key, child) in map.iter_mut() {
+                        match (key.as_str(), child) {
+                            ("rich_text" | "caption", serde_json::Value::Array(runs)) => *runs = merge(runs),
+                            ("cells", serde_json::Value::Array(cells)) => cells.iter_mut().for_each(|cell| {
+                                if let serde_json::Value::Array(runs) = cell {
+                                    *runs = merge(runs);
+                                }
+                            }),
+                            (_, child) => walk(child),
+                        }
+                    }
+                }
+                serde_json::Value::Array(items) => items.iter_mut().for_each(walk),
+                _ => {}
             }
+        }
+        let mut value = serde_json::to_value(blocks).expect("blocks always serialize");
+        walk(&mut value);
+        value
+    }
+
+    fn block_kinds(blocks: &[Block]) -> Vec<&'static str> {
+        blocks
+            .iter()
+            .map(|xs| match &xs.block_type {
+                BlockType::Heading1 { .. } => "h1",
+                BlockType::Heading2 { .. } => "h2",
+                BlockType::Heading3 { heading_3 } if heading_3.is_toggleable == Some(true) => "toggle h3",
+                BlockType::Heading3 { .. } => "h3",
+                BlockType::Paragraph { .. } => "paragraph",
+                BlockType::BulletedListItem { .. } => "bullet",
+                BlockType::TableOfContents { .. } => "toc",
+                _ => "other",
+            })
+            .collect()
+    }
+
+    #[test]
+    fn deep_headings() {
+        let input = include_str!("../fixtures/deep_headings.md");
+        let bold = convert(input);
+        assert_eq!(
+            block_kinds(&bold),
+            vec![
+                "h2",
+                "paragraph",
+                "paragraph",
+                "paragraph",
+                "paragraph",
+                "paragraph",
+                "paragraph",
+                "bullet",
+                "h3",
+                "paragraph"
+            ]
+        );
+
+        let options = ConvertOptions {
+            headings: HeadingPolicy::ShiftUp,
+            ..Default::default()
+        };
+        let shifted = convert_with(input, &options);
+        assert_eq!(
+            block_kinds(&shifted),
+            vec![
+                "h1",
+                "paragraph",
+                "h3",
+                "paragraph",
+                "paragraph",
+                "paragraph",
+                "h3",
+                "bullet",
+                "h2",
+                "paragraph"
+            ]
+        );
+        // There's an H1 here, so nothing moves.
+        let unshifted = convert_with(format!("# Title\n\n{input}").as_str(), &options);
+        assert_eq!(block_kinds(&unshifted)[1..], block_kinds(&bold));
+
+        let options = ConvertOptions {
+            headings: HeadingPolicy::Toggle,
+            ..Default::default()
         };
+        // A `Block` can't hold a toggle heading's contents, so they come right after it.
+        let toggled = convert_with(input, &options);
+        assert_eq!(
+            block_kinds(&toggled),
+            vec![
+                "h2",
+                "paragraph",
+                "toggle h3",
+                "paragraph",
+                "toggle h3",
+                "paragraph",
+                "toggle h3",
+                "bullet",
+                "h3",
+                "paragraph"
+            ]
+        );
+        // The JSON has them inside, the way the page gets them.
+        let (json, _diagnostics) = convert_to_json(input, &options);
+        let kinds: Vec<&str> = json
+            .as_array()
+            .expect("an array of blocks")
+            .iter()
+            .filter_map(|xs| xs["type"].as_str())
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                "heading_2",
+                "paragraph",
+                "heading_3",
+                "heading_3",
+                "heading_3",
+                "paragraph"
+            ]
+        );
+        let body = json[2]["heading_3"]["children"]
+            .as_array()
+            .expect("the toggle has contents");
+        assert_eq!(body.len(), 2);
+        // Including the finer points, inside the details.
+        let finer = body[1]["heading_3"]["children"]
+            .as_array()
+            .expect("the nested toggle has contents");
+        assert_eq!(finer.len(), 1);
     }
 }
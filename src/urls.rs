@@ -0,0 +1,217 @@
+//! Notion is fussy about URLs. It wants them absolute, with a scheme it recognizes, and
+//! it rejects a whole request over one link it doesn't like. Everything we put into a
+//! link or an image goes through here first.
+
+use url::{ParseError, Url};
+
+/// Notion's limit on the length of a link URL.
+const MAX_URL_LENGTH: usize = 2000;
+
+/// What we found when checking a URL.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Checked {
+    /// Notion will take it as it is.
+    Fine(String),
+    /// Notion will take this instead: a relative URL resolved, or an absolute one encoded.
+    Changed(String),
+    /// Notion won't take it at all. The reason reads as the end of a sentence.
+    Rejected(String),
+}
+
+/// Check a URL for a link. Relative URLs resolve against `base`, if there is one. The base
+/// is a directory, whether or not it ends in a slash.
+pub(crate) fn check_link(url: &str, base: Option<&str>) -> Checked {
+    check(url, base, &["http", "https", "ftp", "mailto", "tel"])
+}
+
+/// Check a URL for an external image, which has to be on the web.
+pub(crate) fn check_image(url: &str, base: Option<&str>) -> Checked {
+    check(url, base, &["http", "https"])
+}
+
+fn check(original: &str, base: Option<&str>, schemes: &[&str]) -> Checked {
+    let trimmed = original.trim();
+    if trimmed.is_empty() {
+        return Checked::Rejected("it's empty".to_string());
+    }
+    let parsed = match Url::parse(trimmed) {
+        Ok(parsed) => parsed,
+        Err(ParseError::RelativeUrlWithoutBase) => {
+            let base = base.and_then(|xs| {
+                let directory = if xs.ends_with('/') {
+                    xs.to_string()
+                } else {
+                    format!("{xs}/")
+                };
+                Url::parse(directory.as_str()).ok()
+            });
+            let resolved = match base {
+                Some(base) => base.join(trimmed),
+                // Protocol-relative URLs only need a scheme.
+                None if trimmed.starts_with("//") => Url::parse(format!("https:{trimmed}").as_str()),
+                None => return Checked::Rejected(format!("`{trimmed}` is relative, and there's no base URL")),
+            };
+            match resolved {
+                Ok(resolved) => resolved,
+                Err(_) => return Checked::Rejected(format!("`{trimmed}` isn't a valid URL")),
+            }
+        }
+        Err(_) => return Checked::Rejected(format!("`{trimmed}` isn't a valid URL")),
+    };
+
+    let scheme = parsed.scheme();
+    if !schemes.contains(&scheme) {
+        return Checked::Rejected(format!("Notion doesn't take {scheme}: URLs here"));
+    }
+    let complete = match scheme {
+        "mailto" => parsed.path().contains('@'),
+        "tel" => !parsed.path().is_empty(),
+        _ => parsed.host_str().is_some_and(|host| !host.is_empty()),
+    };
+    if !complete {
+        return Checked::Rejected(format!("`{trimmed}` has nowhere to go"));
+    }
+    if parsed.as_str().len() > MAX_URL_LENGTH {
+        return Checked::Rejected(format!("it's longer than Notion's {MAX_URL_LENGTH} characters"));
+    }
+
+    // Keep the URL as written unless it needed resolving or has characters that
+    // have to be encoded; the parser's idea of a canonical URL isn't worth a report.
+    let needs_encoding = trimmed
+        .chars()
+        .any(|c| c.is_whitespace() || c.is_control() || !c.is_ascii());
+    if trimmed == original && !needs_encoding && Url::parse(trimmed).is_ok() {
+        Checked::Fine(original.to_string())
+    } else {
+        Checked::Changed(parsed.to_string())
+    }
+}
+
+/// How Notion can show a link that sits on its own line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Preview {
+    Bookmark,
+    Video,
+    Pdf,
+    Embed,
+}
+
+/// Sites Notion knows how to embed, beyond the video sites.
+const EMBEDDABLE: &[&str] = &[
+    "codepen.io",
+    "codesandbox.io",
+    "docs.google.com",
+    "drive.google.com",
+    "excalidraw.com",
+    "figma.com",
+    "gist.github.com",
+    "loom.com",
+    "miro.com",
+    "open.spotify.com",
+    "replit.com",
+    "soundcloud.com",
+    "twitter.com",
+    "whimsical.com",
+    "x.com",
+];
+
+/// The best way to show this URL as a block of its own. Only web URLs get one.
+pub(crate) fn preview_for(url: &str) -> Option<Preview> {
+    let parsed = Url::parse(url).ok()?;
+    if !matches!(parsed.scheme(), "http" | "https") {
+        return None;
+    }
+    let host = parsed.host_str()?.trim_start_matches("www.");
+    let on = |site: &str| host == site || host.ends_with(format!(".{site}").as_str());
+    let preview = if on("youtube.com") || on("youtu.be") || on("vimeo.com") {
+        Preview::Video
+    } else if parsed.path().to_lowercase().ends_with(".pdf") {
+        Preview::Pdf
+    } else if EMBEDDABLE.iter().any(|site| on(site)) {
+        Preview::Embed
+    } else {
+        Preview::Bookmark
+    };
+    Some(preview)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn links() {
+        let fine = |url: &str| Checked::Fine(url.to_string());
+        assert_eq!(check_link("https://example.com", None), fine("https://example.com"));
+        assert_eq!(
+            check_link("mailto:ops@example.com", None),
+            fine("mailto:ops@example.com")
+        );
+        assert_eq!(check_link("tel:+15555550100", None), fine("tel:+15555550100"));
+
+        assert_eq!(
+            check_link("docs/setup.md", Some("https://wiki.example.com/team/")),
+            Checked::Changed("https://wiki.example.com/team/docs/setup.md".to_string())
+        );
+        assert_eq!(
+            check_link("../up.html?q=1", Some("https://wiki.example.com/team/pages")),
+            Checked::Changed("https://wiki.example.com/team/up.html?q=1".to_string())
+        );
+        assert_eq!(
+            check_link("//cdn.example.com/x", None),
+            Checked::Changed("https://cdn.example.com/x".to_string())
+        );
+        assert_eq!(
+            check_link(" https://example.com/a b ", None),
+            Checked::Changed("https://example.com/a%20b".to_string())
+        );
+
+        for bad in [
+            "",
+            "   ",
+            "docs/setup.md",
+            "mailto:",
+            "javascript:alert(1)",
+            "file:///etc/hosts",
+            "attachments/report.pdf",
+            "https://",
+        ] {
+            assert!(
+                matches!(check_link(bad, None), Checked::Rejected(_)),
+                "{bad:?} should be rejected"
+            );
+        }
+        let long = format!("https://example.com/{}", "a".repeat(MAX_URL_LENGTH));
+        assert!(matches!(check_link(long.as_str(), None), Checked::Rejected(_)));
+    }
+
+    #[test]
+    fn images() {
+        assert_eq!(
+            check_image("./img/a.png", Some("https://example.com/assets/")),
+            Checked::Changed("https://example.com/assets/img/a.png".to_string())
+        );
+        assert!(matches!(
+            check_image("mailto:ops@example.com", None),
+            Checked::Rejected(_)
+        ));
+        assert!(matches!(
+            check_image("data:image/png;base64,AAAA", None),
+            Checked::Rejected(_)
+        ));
+    }
+
+    #[test]
+    fn previews() {
+        let preview = |url: &str| preview_for(url);
+        assert_eq!(preview("https://www.youtube.com/watch?v=abc"), Some(Preview::Video));
+        assert_eq!(preview("https://youtu.be/abc"), Some(Preview::Video));
+        assert_eq!(preview("https://player.vimeo.com/video/1"), Some(Preview::Video));
+        assert_eq!(preview("https://example.com/files/Report.PDF"), Some(Preview::Pdf));
+        assert_eq!(preview("https://www.figma.com/file/abc"), Some(Preview::Embed));
+        assert_eq!(preview("https://gist.github.com/someone/abc"), Some(Preview::Embed));
+        assert_eq!(preview("https://github.com/someone/abc"), Some(Preview::Bookmark));
+        assert_eq!(preview("https://notyoutube.com/watch"), Some(Preview::Bookmark));
+        assert_eq!(preview("mailto:ops@example.com"), None);
+    }
+}